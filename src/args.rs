@@ -0,0 +1,358 @@
+use crate::lib::args::ModuleArgsError::{
+    DuplicateArgument, InvalidValue, MissingValue, UnexpectedValue, UnknownArgument,
+};
+use crate::lib::conf::{Config, EntryOptions};
+use crate::lib::rssi::RssiFilter;
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// The arguments of the PAM module, as written after the module
+/// in a `/etc/pam.d` service policy.
+///
+/// Unknown arguments and values that can't be parsed are errors, so a
+/// typo doesn't silently fall back to a default, unless the `lenient`
+/// argument is given in which case they are only logged and ignored.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ModuleArgs {
+    /// Specifies the location of the module config.
+    pub config: PathBuf,
+
+    /// Specifies the unlock threshold, the per-user value from the
+    /// config entry takes precedence over this.
+    pub unlock_threshold: Option<i16>,
+
+    /// Specifies how many times to search for the Apple Watch, the
+    /// per-user value from the config entry takes precedence over this.
+    pub retries: Option<u8>,
+
+    /// Specifies how long, in milliseconds, each search may take, the
+    /// per-user value from the config entry takes precedence over this.
+    pub retry_timeout: Option<u64>,
+
+    /// Specifies the Bluetooth adapter, the per-user value from the
+    /// config entry takes precedence over this.
+    pub adapter: Option<String>,
+
+    /// Specifies if the Apple Watch must have auto-unlock enabled, the
+    /// per-user value from the config entry takes precedence over this.
+    pub require_auto_unlock: Option<bool>,
+
+    pub rssi_samples: u8,
+    pub rssi_window: Duration,
+    pub rssi_filter: RssiFilter,
+
+    /// Specifies the maximum time spent searching for all of the
+    /// user's watches.
+    pub max_wait: Option<Duration>,
+
+    /// Specifies the file each unlock decision is appended to.
+    pub decision_log: Option<PathBuf>,
+
+    /// Specifies where messages are logged, when not set journald
+    /// is used if it is running.
+    pub log: Option<LogBackend>,
+
+    pub debug: bool,
+    pub quiet: bool,
+
+    /// Specifies if the session hooks start monitoring the presence
+    /// of the user's Apple Watch for the length of the session.
+    pub monitor: bool,
+
+    /// Specifies if invalid arguments are ignored rather than
+    /// failing authentication.
+    pub lenient: bool,
+}
+
+impl ModuleArgs {
+    pub const CONFIG: &'static str = "config";
+    pub const RSSI_SAMPLES: &'static str = "rssi_samples";
+    pub const RSSI_WINDOW: &'static str = "rssi_window";
+    pub const RSSI_FILTER: &'static str = "rssi_filter";
+    pub const MAX_WAIT: &'static str = "max_wait";
+    pub const DECISION_LOG: &'static str = "decision_log";
+    pub const LOG: &'static str = "log";
+    pub const DEBUG: &'static str = "debug";
+    pub const QUIET: &'static str = "quiet";
+    pub const MONITOR: &'static str = "monitor";
+    pub const LENIENT: &'static str = "lenient";
+
+    const DEFAULT_RSSI_SAMPLES: u8 = 1;
+    const DEFAULT_RSSI_WINDOW_MS: u64 = 1000;
+
+    /// Parses the module arguments, returning them along with the
+    /// problems found with any of them, the arguments with problems
+    /// are left at their defaults.
+    pub fn parse<'a>(args: impl IntoIterator<Item = &'a str>) -> (Self, Vec<ModuleArgsError>) {
+        let mut module_args = Self::default();
+        let mut errors = Vec::new();
+        let mut seen = HashSet::new();
+
+        for arg in args {
+            let (key, value) = match arg.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (arg, None),
+            };
+
+            if !seen.insert(key) {
+                errors.push(DuplicateArgument(key.to_string()));
+                continue;
+            }
+
+            if let Err(err) = module_args.set(key, value) {
+                errors.push(err);
+            }
+        }
+
+        (module_args, errors)
+    }
+
+    /// Sets the argument from its value as written in the policy.
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<(), ModuleArgsError> {
+        match key {
+            Self::CONFIG => self.config = Self::value(key, value)?,
+            EntryOptions::UNLOCK_THRESHOLD => {
+                self.unlock_threshold = Some(Self::value(key, value)?);
+            }
+            EntryOptions::RETRIES => self.retries = Some(Self::count(key, value)?),
+            EntryOptions::RETRY_TIMEOUT => self.retry_timeout = Some(Self::value(key, value)?),
+            EntryOptions::ADAPTER => self.adapter = Some(Self::value(key, value)?),
+            EntryOptions::REQUIRE_AUTO_UNLOCK => {
+                self.require_auto_unlock = Some(Self::value(key, value)?);
+            }
+            Self::RSSI_SAMPLES => self.rssi_samples = Self::count(key, value)?,
+            Self::RSSI_WINDOW => self.rssi_window = Duration::from_millis(Self::value(key, value)?),
+            Self::RSSI_FILTER => self.rssi_filter = Self::value(key, value)?,
+            Self::MAX_WAIT => self.max_wait = Some(Duration::from_millis(Self::value(key, value)?)),
+            Self::DECISION_LOG => self.decision_log = Some(Self::value(key, value)?),
+            Self::LOG => self.log = Some(Self::value(key, value)?),
+            Self::DEBUG => self.debug = Self::flag(key, value)?,
+            Self::QUIET => self.quiet = Self::flag(key, value)?,
+            Self::MONITOR => self.monitor = Self::flag(key, value)?,
+            Self::LENIENT => self.lenient = Self::flag(key, value)?,
+            _ => return Err(UnknownArgument(key.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Parses the value of an argument that requires one.
+    fn value<T: FromStr>(key: &str, value: Option<&str>) -> Result<T, ModuleArgsError> {
+        let value = value.ok_or_else(|| MissingValue(key.to_string()))?;
+        if value.is_empty() {
+            return Err(MissingValue(key.to_string()));
+        }
+
+        value
+            .parse()
+            .map_err(|_| InvalidValue(key.to_string(), value.to_string()))
+    }
+
+    /// Parses the value of an argument that counts how many times to
+    /// do something, e.g. `retries`, which must be done at least once.
+    fn count(key: &str, value: Option<&str>) -> Result<u8, ModuleArgsError> {
+        match Self::value(key, value)? {
+            0 => Err(InvalidValue(key.to_string(), "0".to_string())),
+            count => Ok(count),
+        }
+    }
+
+    /// Checks that a flag argument, e.g. `debug`, wasn't given a value.
+    fn flag(key: &str, value: Option<&str>) -> Result<bool, ModuleArgsError> {
+        match value {
+            None => Ok(true),
+            Some(_) => Err(UnexpectedValue(key.to_string())),
+        }
+    }
+}
+
+impl Default for ModuleArgs {
+    fn default() -> Self {
+        Self {
+            config: PathBuf::from(Config::DEFAULT_LOCATION),
+            unlock_threshold: None,
+            retries: None,
+            retry_timeout: None,
+            adapter: None,
+            require_auto_unlock: None,
+            rssi_samples: Self::DEFAULT_RSSI_SAMPLES,
+            rssi_window: Duration::from_millis(Self::DEFAULT_RSSI_WINDOW_MS),
+            rssi_filter: RssiFilter::default(),
+            max_wait: None,
+            decision_log: None,
+            log: None,
+            debug: false,
+            quiet: false,
+            monitor: false,
+            lenient: false,
+        }
+    }
+}
+
+/// Specifies where the PAM module logs its messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogBackend {
+    /// Writes to syslog, with the `LOG_AUTHPRIV` facility, as
+    /// text with the structured fields appended.
+    Syslog,
+
+    /// Writes to journald using its native protocol, keeping
+    /// the structured fields as journal fields.
+    Journald,
+}
+
+impl FromStr for LogBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "syslog" => Ok(Self::Syslog),
+            "journald" => Ok(Self::Journald),
+            other => Err(format!(
+                "unknown log backend '{other}', expected one of: syslog, journald"
+            )),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ModuleArgsError {
+    #[error("Unknown module argument '{0}'")]
+    UnknownArgument(String),
+
+    #[error("Module argument '{0}' requires a value")]
+    MissingValue(String),
+
+    #[error("Module argument '{0}' doesn't take a value")]
+    UnexpectedValue(String),
+
+    #[error("Module argument '{0}' has an invalid value '{1}'")]
+    InvalidValue(String, String),
+
+    #[error("Module argument '{0}' is given more than once")]
+    DuplicateArgument(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModuleArgs, ModuleArgsError};
+    use crate::lib::rssi::RssiFilter;
+
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn valid_arguments_are_parsed() {
+        let (args, errors) = ModuleArgs::parse([
+            "unlock_threshold=-70",
+            "retries=5",
+            "retry_timeout=750",
+            "adapter=hci1",
+            "require_auto_unlock=false",
+            "rssi_samples=3",
+            "rssi_window=2000",
+            "rssi_filter=kalman",
+            "max_wait=1500",
+            "config=/etc/security/watch.conf",
+            "debug",
+            "monitor",
+        ]);
+
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(args.unlock_threshold, Some(-70));
+        assert_eq!(args.retries, Some(5));
+        assert_eq!(args.retry_timeout, Some(750));
+        assert_eq!(args.adapter.as_deref(), Some("hci1"));
+        assert_eq!(args.require_auto_unlock, Some(false));
+        assert_eq!(args.rssi_samples, 3);
+        assert_eq!(args.rssi_window, Duration::from_secs(2));
+        assert_eq!(args.rssi_filter, RssiFilter::Kalman);
+        assert_eq!(args.max_wait, Some(Duration::from_millis(1500)));
+        assert_eq!(args.config, Path::new("/etc/security/watch.conf"));
+        assert!(args.debug && args.monitor);
+        assert!(!args.quiet && !args.lenient);
+    }
+
+    #[test]
+    fn unknown_arguments_are_errors() {
+        let (_, errors) = ModuleArgs::parse(["unlock_treshold=-70", "verbose"]);
+
+        assert_eq!(errors.len(), 2);
+        assert!(
+            errors
+                .iter()
+                .all(|err| matches!(err, ModuleArgsError::UnknownArgument(_)))
+        );
+    }
+
+    #[test]
+    fn bad_values_are_errors_and_keep_the_default() {
+        let (args, errors) = ModuleArgs::parse([
+            "unlock_threshold=near",
+            "retries=-1",
+            "rssi_samples=0",
+            "rssi_filter=mean",
+            "log=stderr",
+            "rssi_window=",
+            "max_wait",
+            "debug=true",
+            "quiet",
+            "quiet",
+        ]);
+
+        assert!(
+            matches!(&errors[0], ModuleArgsError::InvalidValue(key, value) if key == "unlock_threshold" && value == "near")
+        );
+        assert!(matches!(&errors[1], ModuleArgsError::InvalidValue(key, _) if key == "retries"));
+        assert!(
+            matches!(&errors[2], ModuleArgsError::InvalidValue(key, value) if key == "rssi_samples" && value == "0")
+        );
+        assert!(
+            matches!(&errors[3], ModuleArgsError::InvalidValue(key, _) if key == "rssi_filter")
+        );
+        assert!(matches!(&errors[4], ModuleArgsError::InvalidValue(key, _) if key == "log"));
+        assert!(matches!(&errors[5], ModuleArgsError::MissingValue(key) if key == "rssi_window"));
+        assert!(matches!(&errors[6], ModuleArgsError::MissingValue(key) if key == "max_wait"));
+        assert!(matches!(&errors[7], ModuleArgsError::UnexpectedValue(key) if key == "debug"));
+        assert!(matches!(&errors[8], ModuleArgsError::DuplicateArgument(key) if key == "quiet"));
+        assert_eq!(errors.len(), 9);
+
+        assert_eq!(args.unlock_threshold, None);
+        assert_eq!(args.retries, None);
+        assert_eq!(args.rssi_samples, 1);
+        assert_eq!(args.rssi_filter, RssiFilter::default());
+        assert_eq!(args.rssi_window, Duration::from_secs(1));
+        assert_eq!(args.max_wait, None);
+        assert!(!args.debug);
+        assert!(args.quiet);
+    }
+
+    #[test]
+    fn searching_zero_times_is_an_error() {
+        let (args, errors) = ModuleArgs::parse(["retries=0", "rssi_samples=0"]);
+
+        assert!(
+            matches!(&errors[0], ModuleArgsError::InvalidValue(key, value) if key == "retries" && value == "0")
+        );
+        assert!(
+            matches!(&errors[1], ModuleArgsError::InvalidValue(key, value) if key == "rssi_samples" && value == "0")
+        );
+        assert_eq!(errors.len(), 2);
+        assert_eq!(args.retries, None);
+        assert_eq!(args.rssi_samples, 1);
+    }
+
+    #[test]
+    fn lenient_keeps_the_valid_arguments() {
+        let (args, errors) = ModuleArgs::parse(["typo", "unlock_threshold=-60", "lenient"]);
+
+        assert_eq!(errors.len(), 1);
+        assert!(args.lenient);
+        assert_eq!(args.unlock_threshold, Some(-60));
+    }
+}
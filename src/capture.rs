@@ -0,0 +1,289 @@
+use crate::lib::capture::CaptureError::InvalidRecord;
+use crate::lib::irk::decode_hex;
+use crate::lib::scanner::mock::{MockAdvertisement, MockScanner};
+
+use bluer::Address;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A recording of Bluetooth Low Energy advertisements that can be
+/// attached to a bug report and later replayed, via [`MockScanner`],
+/// to reproduce the decisions made when searching for an Apple Watch.
+///
+/// A device is recorded when it is discovered and again each time its
+/// RSSI, TX power or manufacturer data changes, so the replay follows
+/// the device as the adapter saw it.
+#[derive(Debug)]
+pub struct Capture {
+    pub records: Vec<CaptureRecord>,
+}
+
+impl Capture {
+    /// Specifies the comment written at the start of every capture
+    /// file to describe the format of the records that follow.
+    pub const HEADER: &'static str = concat!(
+        "#\n",
+        "# Apple Watch Unlock advertisement capture\n",
+        "#\n",
+        "# The syntax of the lines is as follows:\n",
+        "#       timestamp;address;rssi;tx_power;manufacturer_data\n",
+        "#\n",
+        "# timestamp\n",
+        "#       Milliseconds since the UNIX epoch when the advertisement, or a change\n",
+        "#       to the RSSI, TX power or manufacturer data of the device, was received\n",
+        "# manufacturer_data\n",
+        "#       Comma separated list of company_id=data, both hex encoded\n",
+        "#",
+    );
+
+    pub fn load(path: &Path) -> Result<Self, CaptureError> {
+        let raw_capture = std::fs::read_to_string(path)?;
+
+        let records = raw_capture
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_number, line)| {
+                line.parse()
+                    .map_err(|reason| InvalidRecord(line_number + 1, reason))
+            })
+            .collect::<Result<Vec<CaptureRecord>, CaptureError>>()?;
+
+        Ok(Self { records })
+    }
+
+    /// Converts the capture into a [`MockScanner`] that replays each
+    /// advertisement with the same spacing, in time, as it was recorded.
+    pub fn into_scanner(self) -> MockScanner {
+        let mut previous_timestamp = self.records.first().map_or(0, |record| record.timestamp);
+
+        let advertisements = self
+            .records
+            .into_iter()
+            .map(|record| {
+                let delay =
+                    Duration::from_millis(record.timestamp.saturating_sub(previous_timestamp));
+                previous_timestamp = record.timestamp;
+
+                MockAdvertisement {
+                    delay,
+                    address: record.address,
+                    rssi: record.rssi,
+                    tx_power: record.tx_power,
+                    manufacturer_data: record.manufacturer_data.into_iter().collect(),
+                }
+            })
+            .collect();
+
+        MockScanner::new(advertisements)
+    }
+}
+
+#[derive(Debug)]
+pub struct CaptureRecord {
+    /// Specifies when, in milliseconds since the UNIX epoch,
+    /// the advertisement was received.
+    pub timestamp: u64,
+
+    /// Specifies the Bluetooth address of the advertising device.
+    pub address: Address,
+
+    /// Specifies the received signal strength indicator of the
+    /// advertisement, if any.
+    pub rssi: Option<i16>,
+
+    /// Specifies the transmission power advertised by the
+    /// device, if any.
+    pub tx_power: Option<i16>,
+
+    /// Specifies the manufacturer specific data included in the
+    /// advertisement keyed by company identifier.
+    pub manufacturer_data: BTreeMap<u16, Vec<u8>>,
+}
+
+impl CaptureRecord {
+    pub fn new(
+        timestamp: u64,
+        address: Address,
+        rssi: Option<i16>,
+        tx_power: Option<i16>,
+        manufacturer_data: HashMap<u16, Vec<u8>>,
+    ) -> Self {
+        Self {
+            timestamp,
+            address,
+            rssi,
+            tx_power,
+            manufacturer_data: manufacturer_data.into_iter().collect(),
+        }
+    }
+
+    fn parse_optional<T: FromStr>(
+        value: &str,
+        reason: &'static str,
+    ) -> Result<Option<T>, &'static str> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        value.parse().map(Some).map_err(|_| reason)
+    }
+}
+
+impl FromStr for CaptureRecord {
+    type Err = &'static str;
+
+    fn from_str(raw_record: &str) -> Result<Self, Self::Err> {
+        let values: Vec<&str> = raw_record.split(';').collect();
+        let [timestamp, address, rssi, tx_power, manufacturer_data] = values[..] else {
+            return Err("expected 5 fields");
+        };
+
+        let mut record = Self {
+            timestamp: timestamp.parse().map_err(|_| "invalid timestamp")?,
+            address: address.parse().map_err(|_| "invalid address")?,
+            rssi: Self::parse_optional(rssi, "invalid RSSI")?,
+            tx_power: Self::parse_optional(tx_power, "invalid TX power")?,
+            manufacturer_data: BTreeMap::new(),
+        };
+
+        for entry in manufacturer_data
+            .split(',')
+            .filter(|entry| !entry.is_empty())
+        {
+            let (company_id, data) = entry
+                .split_once('=')
+                .ok_or("manufacturer data entry must be company_id=data")?;
+
+            let company_id =
+                u16::from_str_radix(company_id, 16).map_err(|_| "invalid company identifier")?;
+            let data = decode_hex(data).ok_or("invalid manufacturer data")?;
+
+            record.manufacturer_data.insert(company_id, data);
+        }
+
+        Ok(record)
+    }
+}
+
+impl Display for CaptureRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};{};", self.timestamp, self.address)?;
+
+        if let Some(rssi) = self.rssi {
+            write!(f, "{rssi}")?;
+        }
+        f.write_char(';')?;
+
+        if let Some(tx_power) = self.tx_power {
+            write!(f, "{tx_power}")?;
+        }
+        f.write_char(';')?;
+
+        for (i, (company_id, data)) in self.manufacturer_data.iter().enumerate() {
+            if i > 0 {
+                f.write_char(',')?;
+            }
+
+            write!(f, "{company_id:04x}=")?;
+            for byte in data {
+                write!(f, "{byte:02x}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("Capture record (line {0}) is invalid: {1}")]
+    InvalidRecord(usize, &'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capture, CaptureRecord};
+    use crate::lib::scanner::{ScannedDevice, Scanner};
+
+    use bluer::{DeviceEvent, DeviceProperty};
+    use futures::StreamExt;
+
+    const ADDRESS: &str = "4A:11:22:95:3D:A2";
+
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let raw_record = format!("1700000000000;{ADDRESS};-60;12;004c=10050198aabbcc");
+        let record: CaptureRecord = raw_record.parse().unwrap();
+
+        assert_eq!(record.rssi, Some(-60));
+        assert_eq!(record.tx_power, Some(12));
+        assert_eq!(record.to_string(), raw_record);
+    }
+
+    #[test]
+    fn invalid_records_are_rejected() {
+        assert!(
+            format!("1;{ADDRESS};-60;")
+                .parse::<CaptureRecord>()
+                .is_err()
+        );
+        assert!(
+            format!("1;{ADDRESS};-60;;004c=1")
+                .parse::<CaptureRecord>()
+                .is_err()
+        );
+        assert!("1;not an address;-60;;".parse::<CaptureRecord>().is_err());
+    }
+
+    #[test]
+    fn replay_follows_device_changes() {
+        let capture = Capture {
+            records: vec![
+                format!("1000;{ADDRESS};-60;12;").parse().unwrap(),
+                format!("1001;{ADDRESS};-55;12;").parse().unwrap(),
+            ],
+        };
+
+        let scanner = capture.into_scanner();
+        run(async {
+            scanner.set_powered(true).await.unwrap();
+            let mut device_events = scanner.discover_devices().await.unwrap();
+            device_events.next().await.unwrap();
+
+            let device = scanner.device(ADDRESS.parse().unwrap()).unwrap();
+            assert_eq!(device.rssi().await.unwrap(), Some(-60));
+            assert_eq!(device.tx_power().await.unwrap(), Some(12));
+
+            // The second record is only replayed whilst discovering
+            let mut property_events = device.events().await.unwrap();
+            let rssi = loop {
+                tokio::select! {
+                    Some(_) = device_events.next() => (),
+                    Some(DeviceEvent::PropertyChanged(property)) = property_events.next() => {
+                        if let DeviceProperty::Rssi(rssi) = property {
+                            break rssi;
+                        }
+                    }
+                }
+            };
+
+            assert_eq!(rssi, -55);
+        });
+    }
+}
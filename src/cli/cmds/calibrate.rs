@@ -0,0 +1,352 @@
+use crate::cmds::{CommandDelegate, find_watch, load_config, search_args};
+use crate::lib::irk::Irk;
+use crate::lib::scanner::Scanner;
+use crate::lib::watch::AppleWatch;
+
+use async_trait::async_trait;
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use std::io::Write;
+use std::time::Duration;
+
+/// Identifies where the watch is while its RSSI is sampled.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// The watch is where it normally is while using the keyboard.
+    Near,
+
+    /// The watch is across the room, where it should no longer unlock.
+    Far,
+}
+
+impl Phase {
+    /// Returns what the user is asked to do with their watch.
+    fn instruction(self) -> &'static str {
+        match self {
+            Self::Near => "Place your Apple Watch where it normally is while using the keyboard",
+            Self::Far => "Move your Apple Watch across the room, where it should no longer unlock",
+        }
+    }
+}
+
+pub struct CalibrateCommand;
+
+impl CalibrateCommand {
+    /// Specifies the percentile of the "at the keyboard" samples
+    /// that must be above the recommended threshold.
+    const NEAR_PERCENTILE: usize = 10;
+
+    /// Specifies the percentile of the "across the room" samples
+    /// that must be below the recommended threshold.
+    const FAR_PERCENTILE: usize = 90;
+
+    /// Asks the user to position their watch and then collects
+    /// RSSI samples for it, returning them sorted.
+    ///
+    /// A watch that can't be heard across the room is as far away as
+    /// it can be, so no samples are returned for it rather than failing.
+    async fn sample_phase<S: Scanner>(
+        adapter: &S,
+        irk: Irk,
+        phase: Phase,
+        samples: u8,
+        window: Duration,
+        args: &ArgMatches,
+    ) -> Option<Vec<i16>> {
+        print!("{}, then press Enter to continue...", phase.instruction());
+        let _ = std::io::stdout().flush();
+        if std::io::stdin().read_line(&mut String::new()).is_err() {
+            eprintln!("Failed to read from standard input");
+            return None;
+        }
+
+        let mut watch = AppleWatch::new(irk);
+
+        println!("Searching for Apple Watch");
+        if let Err(err) = find_watch(&mut watch, adapter, args).await {
+            if phase == Phase::Far {
+                println!("Apple Watch wasn't found across the room, so it can't unlock from there");
+                return Some(Vec::new());
+            }
+
+            eprintln!("Failed to find Apple Watch: {err}");
+            return None;
+        }
+
+        println!(
+            "Collecting up to {samples} RSSI samples over {} seconds",
+            window.as_secs_f64()
+        );
+        let mut rssi_samples = match watch.sample_rssi(adapter, samples, window).await {
+            Ok(rssi_samples) if rssi_samples.is_empty() && phase == Phase::Far => {
+                println!("No RSSI samples were collected across the room");
+                return Some(rssi_samples);
+            }
+            Ok(rssi_samples) if rssi_samples.is_empty() => {
+                eprintln!("No RSSI samples were collected");
+                return None;
+            }
+            Ok(rssi_samples) => rssi_samples,
+            Err(err) => {
+                eprintln!("Failed to sample Apple Watch RSSI: {err}");
+                return None;
+            }
+        };
+
+        rssi_samples.sort_unstable();
+        println!(
+            "Collected {} samples, min: {}, median: {}, max: {}",
+            rssi_samples.len(),
+            rssi_samples[0],
+            Self::percentile(&rssi_samples, 50),
+            rssi_samples[rssi_samples.len() - 1],
+        );
+
+        Some(rssi_samples)
+    }
+
+    /// Returns the nearest-rank percentile of the sorted samples.
+    fn percentile(sorted_samples: &[i16], percentile: usize) -> i16 {
+        let rank = (sorted_samples.len() * percentile).div_ceil(100);
+        sorted_samples[rank.saturating_sub(1)]
+    }
+
+    /// Recommends a threshold half way between the weak end of the
+    /// "at the keyboard" samples and the strong end of the "across
+    /// the room" samples, returning false if the two overlap.
+    ///
+    /// Without any "across the room" samples the watch couldn't be
+    /// heard there at all, so the weak end of the "at the keyboard"
+    /// samples is recommended.
+    fn recommend_threshold(near_samples: &[i16], far_samples: &[i16]) -> (i16, bool) {
+        let near = Self::percentile(near_samples, Self::NEAR_PERCENTILE);
+        if far_samples.is_empty() {
+            return (near, true);
+        }
+
+        let far = Self::percentile(far_samples, Self::FAR_PERCENTILE);
+
+        if near <= far {
+            return (near, false);
+        }
+
+        (far + (near - far) / 2, true)
+    }
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for CalibrateCommand {
+    fn name(&self) -> &'static str {
+        "calibrate"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Calibrates the unlock threshold for a user's Apple Watch")
+            .long_about(concat!(
+                "Guides the user through measuring the received signal strength of their Apple Watch\n",
+                "when it is at the keyboard and when it is across the room, and then recommends an\n",
+                "unlock threshold that separates the two.\n",
+                "\n",
+                "When --write is specified the recommended threshold is saved to the user's entry in\n",
+                "the Apple Watch PAM module config, /etc/security/apple_watch.conf, this requires\n",
+                "root permission (i.e. sudo)."
+            ))
+            .arg(
+                Arg::new("user")
+                    .required(true)
+                    .help("Specifies the configured user whose Apple Watch should be calibrated"),
+            )
+            .arg(
+                Arg::new("label")
+                    .long("label")
+                    .help("Specifies the label of the watch to calibrate when the user has several"),
+            )
+            .arg(
+                Arg::new("samples")
+                    .long("samples")
+                    .value_parser(value_parser!(u8).range(1..))
+                    .default_value("20")
+                    .help("Specifies how many RSSI samples to collect in each phase"),
+            )
+            .arg(
+                Arg::new("rssi-window")
+                    .long("rssi-window")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .default_value("10000")
+                    .help("Specifies the maximum time, in milliseconds, spent collecting samples in each phase"),
+            )
+            .arg(
+                Arg::new("adapter")
+                    .long("adapter")
+                    .help("Specifies the Bluetooth adapter, e.g. hci0, unless set in the user's config entry (default: system default)"),
+            )
+            .arg(
+                Arg::new("write")
+                    .long("write")
+                    .action(ArgAction::SetTrue)
+                    .help("Saves the recommended threshold to the user's configuration entry"),
+            )
+            .args(search_args())
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let user: &String = args.get_one("user").expect("required argument");
+        let label: Option<&str> = args.get_one::<String>("label").map(String::as_str);
+        let samples: &u8 = args.get_one("samples").expect("has default");
+        let window = Duration::from_millis(*args.get_one("rssi-window").expect("has default"));
+        let adapter: Option<&String> = args.get_one("adapter");
+
+        println!("Loading configuration for Apple Watch PAM module");
+        let mut config = match load_config(args) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Failed to load configuration: {err}");
+                return 1;
+            }
+        };
+
+        let Some(entry) = config.get_user(user, label) else {
+            eprintln!("No config entry for '{user}', add one with `add_user` first");
+            return 1;
+        };
+
+        // Always update the entry that was calibrated, even if no label
+        // was supplied and the user has several watches.
+        let label = entry.options.label();
+        if let Some(label) = &label {
+            println!("Calibrating Apple Watch '{label}'");
+        }
+
+        let raw_irk = match entry.irk() {
+            Ok(raw_irk) => raw_irk,
+            Err(err) => {
+                eprintln!("Failed to decode IRK: {err}");
+                return 1;
+            }
+        };
+
+        println!("Creating Bluetooth session");
+        let session = match bluer::Session::new().await {
+            Ok(session) => session,
+            Err(err) => {
+                eprintln!("Failed to create Bluetooth session: {err}");
+                return 1;
+            }
+        };
+
+        // The watch is calibrated with the adapter it is searched for
+        // with, the per-user value from the config entry is preferred.
+        let adapter = if let Some(name) = entry.options.adapter().or_else(|| adapter.cloned()) {
+            println!("Selecting Bluetooth adapter {name}");
+            session.adapter(&name)
+        } else {
+            println!("Selecting default Bluetooth adapter");
+            session.default_adapter().await
+        };
+
+        let adapter = match adapter {
+            Ok(adapter) => adapter,
+            Err(err) => {
+                eprintln!("Failed to obtain access to Bluetooth adapter: {err}");
+                return 1;
+            }
+        };
+
+        let Some(near_samples) =
+            Self::sample_phase(&adapter, raw_irk, Phase::Near, *samples, window, args).await
+        else {
+            return 1;
+        };
+
+        let Some(far_samples) =
+            Self::sample_phase(&adapter, raw_irk, Phase::Far, *samples, window, args).await
+        else {
+            return 1;
+        };
+
+        let (unlock_threshold, separated) = Self::recommend_threshold(&near_samples, &far_samples);
+        if !separated {
+            println!(
+                "WARN: The signal strength at the keyboard and across the room overlap, the watch may unlock from further away than expected"
+            );
+        }
+
+        println!("Recommended unlock threshold: {unlock_threshold}");
+        if !args.get_flag("write") {
+            println!("Re-run with --write to save it to the configuration for '{user}'");
+            return 0;
+        }
+
+        if !config.set_user_unlock_threshold(user, label.as_deref(), unlock_threshold) {
+            eprintln!(
+                "The entry for '{user}' is defined in a drop-in config, add `unlock_threshold={unlock_threshold}` to it manually"
+            );
+            return 1;
+        }
+
+        println!("Saving configuration");
+        if let Err(err) = config.save() {
+            eprintln!("Failed to save configuration: {err}");
+            return 1;
+        }
+
+        println!("Configuration saved successfully");
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CalibrateCommand;
+
+    #[test]
+    fn percentile_uses_the_nearest_rank() {
+        let samples = [-70, -65, -60];
+        assert_eq!(CalibrateCommand::percentile(&samples, 10), -70);
+        assert_eq!(CalibrateCommand::percentile(&samples, 50), -65);
+        assert_eq!(CalibrateCommand::percentile(&samples, 90), -60);
+        assert_eq!(CalibrateCommand::percentile(&samples, 100), -60);
+
+        for percentile in [0, 10, 50, 90, 100] {
+            assert_eq!(CalibrateCommand::percentile(&[-72], percentile), -72);
+        }
+    }
+
+    #[test]
+    fn separated_phases_recommend_the_midpoint() {
+        assert_eq!(
+            CalibrateCommand::recommend_threshold(&[-60, -58, -55], &[-85, -80, -78]),
+            (-69, true)
+        );
+        assert_eq!(
+            CalibrateCommand::recommend_threshold(&[-60], &[-75]),
+            (-68, true)
+        );
+    }
+
+    #[test]
+    fn overlapping_phases_recommend_the_weak_end_at_the_keyboard() {
+        assert_eq!(
+            CalibrateCommand::recommend_threshold(&[-75, -70, -65], &[-80, -72, -68]),
+            (-75, false)
+        );
+        assert_eq!(
+            CalibrateCommand::recommend_threshold(&[-80], &[-60]),
+            (-80, false)
+        );
+
+        // The same strength in both phases can't be told apart
+        assert_eq!(
+            CalibrateCommand::recommend_threshold(&[-70], &[-70]),
+            (-70, false)
+        );
+    }
+
+    #[test]
+    fn watch_missing_across_the_room_is_separated() {
+        assert_eq!(
+            CalibrateCommand::recommend_threshold(&[-62, -60, -58], &[]),
+            (-62, true)
+        );
+    }
+}
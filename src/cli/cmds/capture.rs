@@ -0,0 +1,197 @@
+use crate::cmds::CommandDelegate;
+use crate::lib::capture::{Capture, CaptureRecord};
+
+use async_trait::async_trait;
+use bluer::{
+    Adapter, AdapterEvent, Address, DeviceEvent, DeviceProperty, DiscoveryFilter,
+    DiscoveryTransport,
+};
+use clap::{Arg, ArgMatches, Command, value_parser};
+use futures::StreamExt;
+use futures::stream::SelectAll;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct CaptureCommand;
+
+impl CaptureCommand {
+    const DEFAULT_DURATION_SECS: &'static str = "10";
+
+    /// Returns a record of the current advertisement of the device.
+    async fn record(adapter: &Adapter, addr: Address) -> Option<CaptureRecord> {
+        let device = adapter.device(addr).ok()?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| {
+                u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+            });
+
+        Some(CaptureRecord::new(
+            timestamp,
+            addr,
+            device.rssi().await.ok().flatten(),
+            device.tx_power().await.ok().flatten(),
+            device
+                .manufacturer_data()
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or_default(),
+        ))
+    }
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for CaptureCommand {
+    fn name(&self) -> &'static str {
+        "capture"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Records Bluetooth Low Energy advertisements to a capture file")
+            .long_about(concat!(
+                "Records every Bluetooth Low Energy advertisement received by the default\n",
+                "adapter, and each change to the RSSI, TX power or manufacturer data of the\n",
+                "advertising devices, to a capture file, the capture can then be replayed\n",
+                "with `query_status --replay` to reproduce the decision for an Apple Watch.\n",
+                "\n",
+                "NOTE: The capture will contain advertisements from all nearby devices."
+            ))
+            .arg(
+                Arg::new("output")
+                    .required(true)
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Specifies the file to write the capture to"),
+            )
+            .arg(
+                Arg::new("duration")
+                    .long("duration")
+                    .value_parser(value_parser!(u64))
+                    .default_value(Self::DEFAULT_DURATION_SECS)
+                    .help("Specifies how long, in seconds, to record advertisements for"),
+            )
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let output: &PathBuf = args.get_one("output").expect("required argument");
+        let duration: &u64 = args.get_one("duration").expect("has default");
+
+        let mut capture = match File::create(output) {
+            Ok(file) => BufWriter::new(file),
+            Err(err) => {
+                eprintln!("Failed to create capture file: {err}");
+                return 1;
+            }
+        };
+
+        if let Err(err) = writeln!(capture, "{}", Capture::HEADER) {
+            eprintln!("Failed to write capture file: {err}");
+            return 1;
+        }
+
+        println!("Creating Bluetooth session");
+        let session = match bluer::Session::new().await {
+            Ok(session) => session,
+            Err(err) => {
+                eprintln!("Failed to create Bluetooth session: {err}");
+                return 1;
+            }
+        };
+
+        println!("Selecting default Bluetooth adapter");
+        let adapter = match session.default_adapter().await {
+            Ok(adapter) => adapter,
+            Err(err) => {
+                eprintln!("Failed to obtain access to default Bluetooth adapter: {err}");
+                return 1;
+            }
+        };
+
+        if let Err(err) = adapter.set_powered(true).await {
+            eprintln!("Failed to power-on adapter: {err}");
+            return 1;
+        }
+
+        if let Err(err) = adapter
+            .set_discovery_filter(DiscoveryFilter {
+                transport: DiscoveryTransport::Le,
+                duplicate_data: true,
+                ..Default::default()
+            })
+            .await
+        {
+            eprintln!("Failed to configure BT-LE discovery filter: {err}");
+            return 1;
+        }
+
+        let mut device_events = match adapter.discover_devices().await {
+            Ok(device_events) => device_events,
+            Err(err) => {
+                eprintln!("Failed to discover devices: {err}");
+                return 1;
+            }
+        };
+
+        println!("Recording advertisements for {duration} seconds");
+        let deadline = tokio::time::sleep(Duration::from_secs(*duration));
+        tokio::pin!(deadline);
+
+        // The adapter only announces each device once, the changes to
+        // its advertisements are followed through the device's events.
+        let mut property_events = SelectAll::new();
+        let mut recorded: usize = 0;
+        loop {
+            let addr = tokio::select! {
+                () = &mut deadline => break,
+                Some(device_event) = device_events.next() => {
+                    let AdapterEvent::DeviceAdded(addr) = device_event else {
+                        continue;
+                    };
+
+                    if let Ok(device) = adapter.device(addr)
+                        && let Ok(events) = device.events().await
+                    {
+                        property_events.push(events.map(move |event| (addr, event)));
+                    }
+
+                    addr
+                }
+                Some((addr, DeviceEvent::PropertyChanged(property))) = property_events.next() => {
+                    if !matches!(
+                        property,
+                        DeviceProperty::Rssi(_)
+                            | DeviceProperty::TxPower(_)
+                            | DeviceProperty::ManufacturerData(_)
+                    ) {
+                        continue;
+                    }
+
+                    addr
+                }
+            };
+
+            let Some(record) = Self::record(&adapter, addr).await else {
+                continue;
+            };
+
+            if let Err(err) = writeln!(capture, "{record}") {
+                eprintln!("Failed to write capture file: {err}");
+                return 1;
+            }
+
+            recorded += 1;
+        }
+
+        if let Err(err) = capture.flush() {
+            eprintln!("Failed to write capture file: {err}");
+            return 1;
+        }
+
+        println!("Recorded {recorded} advertisements to {}", output.display());
+        0
+    }
+}
@@ -0,0 +1,70 @@
+use crate::cmds::{CommandDelegate, config_path};
+use crate::lib::conf::Config;
+use crate::lib::conf::check::Severity;
+
+use async_trait::async_trait;
+use clap::{ArgMatches, Command};
+
+pub struct CheckConfigCommand;
+
+impl CheckConfigCommand {
+    /// Specifies the exit status when the PAM module would
+    /// refuse to load the config, or skip one of its entries.
+    const EXIT_ERRORS: i32 = 1;
+
+    /// Specifies the exit status when the config would
+    /// load but may not behave as intended.
+    const EXIT_WARNINGS: i32 = 2;
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for CheckConfigCommand {
+    fn name(&self) -> &'static str {
+        "check_config"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Checks the Apple Watch PAM module configuration for problems")
+            .long_about(concat!(
+                "Checks the Apple Watch PAM module config, /etc/security/apple_watch.conf, and any\n",
+                "drop-in configs, reporting every problem found: malformed entries, insecure file\n",
+                "permissions, users or groups that don't exist, IRKs shared between users and options\n",
+                "that are out of range.\n",
+                "\n",
+                "The exit status is 0 if no problems were found, 1 if the PAM module would refuse to\n",
+                "load the config, or skip one of its entries, and 2 if the config would load but only has\n",
+                "warnings.\n",
+                "\n",
+                "This command requires root permission (i.e. sudo) to read the configuration file."
+            ))
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let issues = Config::check(config_path(args));
+
+        let mut errors: usize = 0;
+        let mut warnings: usize = 0;
+        for issue in &issues {
+            match issue.severity {
+                Severity::Error => {
+                    errors += 1;
+                    println!("ERROR: {issue}");
+                }
+                Severity::Warning => {
+                    warnings += 1;
+                    println!("WARN: {issue}");
+                }
+            }
+        }
+
+        println!("Found {errors} errors and {warnings} warnings");
+        if errors > 0 {
+            Self::EXIT_ERRORS
+        } else if warnings > 0 {
+            Self::EXIT_WARNINGS
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::cmds::{CommandDelegate, load_config};
+use crate::lib::conf::Entry;
+use crate::lib::import::{ImportedDevice, bluez, keychain};
+
+use async_trait::async_trait;
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use std::io::Write;
+use std::path::PathBuf;
+
+pub struct ImportIrkCommand;
+
+impl ImportIrkCommand {
+    /// Prints the prompt and returns the line entered by the user,
+    /// without the trailing newline.
+    fn prompt(prompt: &str) -> Option<String> {
+        print!("{prompt}");
+        let _ = std::io::stdout().flush();
+
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).is_err() {
+            eprintln!("Failed to read from standard input");
+            return None;
+        }
+
+        Some(line.trim().to_string())
+    }
+
+    /// Selects the device to import, either the device named by the
+    /// `--device` argument, the only device found or one picked by the
+    /// user from the list.
+    fn select_device<'a>(
+        devices: &'a [ImportedDevice],
+        args: &ArgMatches,
+    ) -> Option<&'a ImportedDevice> {
+        if let Some(selected) = args.get_one::<String>("device") {
+            let device = devices.iter().find(|device| {
+                device.source == *selected
+                    || device.source.ends_with(&format!("/{selected}"))
+                    || device.name.as_ref() == Some(selected)
+            });
+
+            if device.is_none() {
+                eprintln!("No device matching '{selected}' was found");
+            }
+
+            return device;
+        }
+
+        if let [device] = devices {
+            return Some(device);
+        }
+
+        let selection = Self::prompt(&format!("Select a device [1-{}]: ", devices.len()))?;
+        match selection.parse::<usize>() {
+            Ok(index) if (1..=devices.len()).contains(&index) => Some(&devices[index - 1]),
+            _ => {
+                eprintln!("Invalid selection '{selection}'");
+                None
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for ImportIrkCommand {
+    fn name(&self) -> &'static str {
+        "import_irk"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Imports an Identity Resolution Key from BlueZ or a macOS keychain export")
+            .long_about(concat!(
+                "Lists the paired devices that have an Identity Resolution Key in the BlueZ pairing\n",
+                "database, /var/lib/bluetooth, or in a macOS keychain export given with --keychain, and\n",
+                "when --user is specified adds the key of the selected device to the user's entry in\n",
+                "the Apple Watch PAM module config.\n",
+                "\n",
+                "The keychain export may be the XML property list, or its hex encoding as printed by\n",
+                "`security find-generic-password -w`.\n",
+                "\n",
+                "This command requires root permission (i.e. sudo) to read the pairing database."
+            ))
+            .arg(
+                Arg::new("bluez-dir")
+                    .long("bluez-dir")
+                    .value_parser(value_parser!(PathBuf))
+                    .default_value(bluez::STORAGE_DIR)
+                    .help("Specifies the BlueZ storage directory, or the directory of a single adapter"),
+            )
+            .arg(
+                Arg::new("keychain")
+                    .long("keychain")
+                    .value_parser(value_parser!(PathBuf))
+                    .conflicts_with("bluez-dir")
+                    .help("Specifies a macOS keychain export to import from instead of BlueZ"),
+            )
+            .arg(
+                Arg::new("user")
+                    .long("user")
+                    .help("Specifies the user to add the imported key to"),
+            )
+            .arg(
+                Arg::new("device")
+                    .long("device")
+                    .requires("user")
+                    .help("Specifies the address, or name, of the device to import instead of prompting"),
+            )
+            .arg(
+                Arg::new("label")
+                    .long("label")
+                    .requires("user")
+                    .help("Specifies a name that distinguishes this watch from the user's other watches"),
+            )
+            .arg(
+                Arg::new("yes")
+                    .long("yes")
+                    .action(ArgAction::SetTrue)
+                    .requires("user")
+                    .help("Adds the key without asking for confirmation"),
+            )
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let label: Option<&str> = args.get_one::<String>("label").map(String::as_str);
+
+        let mut skipped = Vec::new();
+        let devices = if let Some(keychain_path) = args.get_one::<PathBuf>("keychain") {
+            println!("Reading macOS keychain export {}", keychain_path.display());
+            keychain::load_devices(keychain_path)
+        } else {
+            let bluez_dir: &PathBuf = args.get_one("bluez-dir").expect("has default");
+            println!("Reading BlueZ pairing database {}", bluez_dir.display());
+            bluez::load_devices(bluez_dir, &mut skipped)
+        };
+
+        let devices = match devices {
+            Ok(devices) => devices,
+            Err(err) => {
+                eprintln!("Failed to read paired devices: {err}");
+                return 1;
+            }
+        };
+
+        for err in &skipped {
+            println!("WARN: Skipping paired device, {err}");
+        }
+
+        if devices.is_empty() {
+            eprintln!("No paired devices with an Identity Resolution Key were found");
+            return 1;
+        }
+
+        for (i, device) in devices.iter().enumerate() {
+            let name = device.name.as_deref().unwrap_or("Unknown device");
+            println!("[{}] {name} ({})", i + 1, device.source);
+        }
+
+        let Some(user) = args.get_one::<String>("user") else {
+            println!("Re-run with --user to add one of these keys to a user's configuration");
+            return 0;
+        };
+
+        let Some(device) = Self::select_device(&devices, args) else {
+            return 1;
+        };
+
+        let irk = device.irk.to_base64();
+        if let Err(err) = Entry::check_fields(user, &irk, label) {
+            eprintln!("{err}");
+            return 1;
+        }
+
+        let name = device.name.as_deref().unwrap_or(&device.source);
+        if !args.get_flag("yes") {
+            let Some(answer) = Self::prompt(&format!(
+                "Add the key of '{name}' to the user '{user}'? [y/N]: "
+            )) else {
+                return 1;
+            };
+
+            if !answer.eq_ignore_ascii_case("y") {
+                println!("Not adding the key");
+                return 1;
+            }
+        }
+
+        println!("Loading configuration for Apple Watch PAM module");
+        let mut config = match load_config(args) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Failed to load configuration: {err}");
+                return 1;
+            }
+        };
+
+        println!("Adding the key of '{name}' to user '{user}'");
+        match config.update_user(user, &irk, label) {
+            Ok(true) => println!("WARN: User already existed, updating existing entry"),
+            Ok(false) => {}
+            Err(err) => {
+                eprintln!("Failed to add user: {err}");
+                return 1;
+            }
+        }
+
+        println!("Saving configuration");
+        if let Err(err) = config.save() {
+            eprintln!("Failed to save configuration: {err}");
+            return 1;
+        }
+
+        println!("Configuration saved successfully");
+        0
+    }
+}
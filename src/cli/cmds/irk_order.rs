@@ -0,0 +1,152 @@
+use crate::cmds::{CommandDelegate, find_watch, search_args};
+use crate::lib::capture::Capture;
+use crate::lib::irk::Irk;
+use crate::lib::scanner::Scanner;
+use crate::lib::watch::AppleWatch;
+
+use async_trait::async_trait;
+use bluer::Address;
+use clap::{Arg, ArgMatches, Command, value_parser};
+use std::path::PathBuf;
+
+pub struct IrkOrderCommand;
+
+impl IrkOrderCommand {
+    /// Returns the IRK as it was written and with its bytes reversed,
+    /// along with a description of each.
+    fn candidates(irk: Irk) -> [(&'static str, Irk); 2] {
+        [
+            ("as written", irk),
+            ("with its bytes reversed", irk.reversed()),
+        ]
+    }
+
+    /// Checks which of the two byte orders of the IRK resolves the
+    /// supplied address, without searching for the watch.
+    fn detect_order_for_address(address: Address, irk: Irk) -> i32 {
+        for (description, candidate) in Self::candidates(irk) {
+            if candidate.resolves(address) {
+                println!("The address {address} resolves using the IRK {description}");
+                println!("Use the IRK {} in the configuration", candidate.to_base64());
+                return 0;
+            }
+        }
+
+        println!("The IRK doesn't resolve the address {address} in either byte order");
+        1
+    }
+
+    /// Searches for an Apple Watch matching the IRK as it was written
+    /// and then with its bytes reversed, reporting which of the two
+    /// byte orders resolves the address of the watch.
+    async fn detect_order<S: Scanner>(adapter: &S, irk: Irk, args: &ArgMatches) -> i32 {
+        for (description, candidate) in Self::candidates(irk) {
+            println!("Searching for Apple Watch using the IRK {description}");
+
+            let mut watch = AppleWatch::new(candidate);
+            if let Err(err) = find_watch(&mut watch, adapter, args).await {
+                println!("No Apple Watch found using the IRK {description}: {err}");
+                continue;
+            }
+
+            println!(
+                "Found Apple Watch {} using the IRK {description}",
+                watch.get_watch_address()
+            );
+            println!("Use the IRK {} in the configuration", candidate.to_base64());
+            return 0;
+        }
+
+        println!("The IRK doesn't match any nearby Apple Watch in either byte order");
+        1
+    }
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for IrkOrderCommand {
+    fn name(&self) -> &'static str {
+        "detect_irk_order"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Detects the byte order of an Identity Resolution Key")
+            .long_about(concat!(
+                "Searches for a nearby Apple Watch using the Identity Resolution Key as it was\n",
+                "written and then with its bytes reversed, as tools differ in the order they print\n",
+                "the bytes of the key, and reports which order matches along with the key in the\n",
+                "form expected by the configuration.\n",
+                "\n",
+                "When --address is specified the address, e.g. as shown by `bluetoothctl devices`, is\n",
+                "resolved using each byte order instead of searching for the watch."
+            ))
+            .arg(
+                Arg::new("irk")
+                    .required(true)
+                    .help("Identity Resolution Key, in Base64 or hex, of the Apple Watch to search for"),
+            )
+            .arg(
+                Arg::new("replay")
+                    .long("replay")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Replays a capture file, recorded by `capture`, instead of using the Bluetooth adapter"),
+            )
+            .arg(
+                Arg::new("address")
+                    .long("address")
+                    .value_parser(value_parser!(Address))
+                    .conflicts_with("replay")
+                    .help("Specifies a resolvable private address of the watch to check instead of searching"),
+            )
+            .args(search_args())
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let encoded_irk: &String = args.get_one("irk").expect("required argument");
+
+        let irk: Irk = match encoded_irk.parse() {
+            Ok(irk) => irk,
+            Err(err) => {
+                eprintln!("Failed to decode IRK: {err}");
+                return 1;
+            }
+        };
+
+        if let Some(address) = args.get_one::<Address>("address") {
+            return Self::detect_order_for_address(*address, irk);
+        }
+
+        if let Some(capture_path) = args.get_one::<PathBuf>("replay") {
+            println!("Loading capture {}", capture_path.display());
+            let capture = match Capture::load(capture_path) {
+                Ok(capture) => capture,
+                Err(err) => {
+                    eprintln!("Failed to load capture: {err}");
+                    return 1;
+                }
+            };
+
+            return Self::detect_order(&capture.into_scanner(), irk, args).await;
+        }
+
+        println!("Creating Bluetooth session");
+        let session = match bluer::Session::new().await {
+            Ok(session) => session,
+            Err(err) => {
+                eprintln!("Failed to create Bluetooth session: {err}");
+                return 1;
+            }
+        };
+
+        println!("Selecting default Bluetooth adapter");
+        let adapter = match session.default_adapter().await {
+            Ok(adapter) => adapter,
+            Err(err) => {
+                eprintln!("Failed to obtain access to default Bluetooth adapter: {err}");
+                return 1;
+            }
+        };
+
+        Self::detect_order(&adapter, irk, args).await
+    }
+}
@@ -0,0 +1,78 @@
+use crate::cmds::{CommandDelegate, load_config};
+use crate::lib::conf::EntryKind;
+
+use async_trait::async_trait;
+use clap::{ArgMatches, Command};
+
+pub struct ListUsersCommand;
+
+impl ListUsersCommand {
+    /// Specifies how many characters of an encoded IRK are
+    /// shown, the remaining characters are masked.
+    const VISIBLE_IRK_CHARS: usize = 4;
+
+    /// Masks all but the first few characters of the encoded
+    /// IRK so that the key isn't revealed on screen.
+    fn mask_irk(encoded_irk: &str) -> String {
+        encoded_irk
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if i < Self::VISIBLE_IRK_CHARS { c } else { '*' })
+            .collect()
+    }
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for ListUsersCommand {
+    fn name(&self) -> &'static str {
+        "list_users"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Lists the users in the Apple Watch PAM module configuration")
+            .long_about(concat!(
+                "Lists every entry in the Apple Watch PAM module config, /etc/security/apple_watch.conf,\n",
+                "along with the options of each entry, the Identity Resolution Keys are masked.\n",
+                "\n",
+                "This command requires root permission (i.e. sudo) to read the configuration file."
+            ))
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let config = match load_config(args) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Failed to load configuration: {err}");
+                return 1;
+            }
+        };
+
+        if config.entries.is_empty() {
+            println!("No users are configured");
+            return 0;
+        }
+
+        for entry in &config.entries {
+            let kind = match entry.kind() {
+                EntryKind::User => "user",
+                EntryKind::Group => "group",
+                EntryKind::Wildcard => "wildcard",
+            };
+
+            match entry.drop_in() {
+                Some(drop_in) => println!("{} ({kind}, from {})", entry.user, drop_in.display()),
+                None => println!("{} ({kind})", entry.user),
+            }
+            println!(
+                "\tIRK.................: {}",
+                Self::mask_irk(&entry.encoded_irk)
+            );
+            for (key, value) in entry.options.iter() {
+                println!("\t{key:.<20}: {value}");
+            }
+        }
+
+        0
+    }
+}
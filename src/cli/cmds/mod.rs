@@ -1,13 +1,36 @@
+mod calibrate;
+mod capture;
+mod check_config;
+mod import_irk;
+mod irk_order;
+mod list_users;
+mod monitor;
 mod pam_test;
 mod query_status;
+mod remove_user;
 mod user;
 
+use crate::cmds::calibrate::CalibrateCommand;
+use crate::cmds::capture::CaptureCommand;
+use crate::cmds::check_config::CheckConfigCommand;
+use crate::cmds::import_irk::ImportIrkCommand;
+use crate::cmds::irk_order::IrkOrderCommand;
+use crate::cmds::list_users::ListUsersCommand;
+use crate::cmds::monitor::MonitorCommand;
 use crate::cmds::pam_test::PAMTestCommand;
 use crate::cmds::query_status::QueryStatusCommand;
+use crate::cmds::remove_user::RemoveUserCommand;
 use crate::cmds::user::UserCommand;
 
+use crate::lib::conf::{Config, ConfigError};
+use crate::lib::scanner::Scanner;
+use crate::lib::watch::AppleWatch;
+
 use async_trait::async_trait;
-use clap::{ArgMatches, Command};
+use clap::{Arg, ArgMatches, Command, value_parser};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::timeout;
 
 #[async_trait(?Send)]
 pub trait CommandDelegate {
@@ -18,10 +41,94 @@ pub trait CommandDelegate {
     async fn execute(&self, args: &ArgMatches) -> i32;
 }
 
-pub fn commands() -> [Box<dyn CommandDelegate>; 3] {
+pub fn commands() -> [Box<dyn CommandDelegate>; 11] {
     [
         Box::new(QueryStatusCommand),
         Box::new(PAMTestCommand),
         Box::new(UserCommand),
+        Box::new(RemoveUserCommand),
+        Box::new(ListUsersCommand),
+        Box::new(CheckConfigCommand),
+        Box::new(CaptureCommand),
+        Box::new(CalibrateCommand),
+        Box::new(IrkOrderCommand),
+        Box::new(ImportIrkCommand),
+        Box::new(MonitorCommand),
+    ]
+}
+
+/// Returns the location of the Apple Watch PAM module config,
+/// as selected by the global `--config` argument.
+pub fn config_path(args: &ArgMatches) -> &Path {
+    args.get_one::<PathBuf>("config").expect("has default")
+}
+
+/// Loads the Apple Watch PAM module config, warning about each
+/// entry that is skipped because it is invalid.
+pub fn load_config(args: &ArgMatches) -> Result<Config, ConfigError> {
+    let config = Config::load(config_path(args))?;
+    for skipped in &config.skipped {
+        println!("WARN: Skipping invalid config entry, {skipped}");
+    }
+
+    Ok(config)
+}
+
+/// Returns the arguments that control how long the search for an Apple
+/// Watch may take, matching the `retries`, `retry_timeout` and `max_wait`
+/// arguments of the PAM module.
+pub fn search_args() -> [Arg; 3] {
+    [
+        Arg::new("retries")
+            .long("retries")
+            .value_parser(value_parser!(u8).range(1..))
+            .default_value("3")
+            .help("Specifies how many times to search for the Apple Watch"),
+        Arg::new("retry-timeout")
+            .long("retry-timeout")
+            .value_parser(value_parser!(u64))
+            .default_value("500")
+            .help("Specifies how long, in milliseconds, each search for the Apple Watch may take"),
+        Arg::new("max-wait")
+            .long("max-wait")
+            .value_parser(value_parser!(u64))
+            .help(
+                "Specifies the maximum time, in milliseconds, spent searching across all retries",
+            ),
     ]
 }
+
+/// Searches for the Apple Watch as controlled by the [`search_args`],
+/// returning the number of tries it took to find the watch.
+pub async fn find_watch<S: Scanner>(
+    watch: &mut AppleWatch<S::Device>,
+    adapter: &S,
+    args: &ArgMatches,
+) -> Result<u8, String> {
+    let retries: &u8 = args.get_one("retries").expect("has default");
+    let retry_timeout: &u64 = args.get_one("retry-timeout").expect("has default");
+    let max_wait: Option<&u64> = args.get_one("max-wait");
+
+    search_watch(watch, adapter, *retries, *retry_timeout, max_wait.copied()).await
+}
+
+/// Searches for the Apple Watch, with each search taking up to the retry
+/// timeout and every search together up to the maximum wait, both in
+/// milliseconds, returning the number of tries it took to find the watch.
+pub async fn search_watch<S: Scanner>(
+    watch: &mut AppleWatch<S::Device>,
+    adapter: &S,
+    retries: u8,
+    retry_timeout: u64,
+    max_wait: Option<u64>,
+) -> Result<u8, String> {
+    let search = watch.find_watch(adapter, retries, Duration::from_millis(retry_timeout));
+    let result = match max_wait {
+        Some(max_wait) => timeout(Duration::from_millis(max_wait), search)
+            .await
+            .map_err(|_| format!("Apple Watch search exceeded the maximum wait of {max_wait}ms"))?,
+        None => search.await,
+    };
+
+    result.map_err(|err| err.to_string())
+}
@@ -0,0 +1,287 @@
+use crate::cmds::{CommandDelegate, load_config, search_args, search_watch};
+use crate::lib::conf::Entry;
+use crate::lib::irk::Irk;
+use crate::lib::watch::AppleWatch;
+
+use async_trait::async_trait;
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use std::time::{Duration, SystemTime};
+
+/// An Apple Watch whose presence is monitored.
+struct MonitoredWatch {
+    label: Option<String>,
+    irk: Irk,
+    adapter: bluer::Adapter,
+    unlock_threshold: i16,
+    require_auto_unlock: bool,
+    retries: u8,
+
+    /// Specifies how long, in milliseconds, each search may take.
+    retry_timeout: u64,
+}
+
+pub struct MonitorCommand;
+
+impl MonitorCommand {
+    /// Returns true if any of the watches is close enough, unlocked
+    /// and able to auto-unlock, that the user is still at this device.
+    async fn is_present(watches: &[MonitoredWatch], args: &ArgMatches) -> bool {
+        for monitored in watches {
+            let mut watch = AppleWatch::new(monitored.irk);
+            let max_wait: Option<&u64> = args.get_one("max-wait");
+            if search_watch(
+                &mut watch,
+                &monitored.adapter,
+                monitored.retries,
+                monitored.retry_timeout,
+                max_wait.copied(),
+            )
+            .await
+            .is_err()
+            {
+                continue;
+            }
+
+            match watch.get_watch_status().await {
+                Ok(status)
+                    if status.rssi >= monitored.unlock_threshold
+                        && !status.locked
+                        && (status.device_auto_unlock_enabled
+                            || !monitored.require_auto_unlock) =>
+                {
+                    return true;
+                }
+                Ok(status) => println!(
+                    "Apple Watch {} is not present (rssi {}, locked {}, auto-unlock {})",
+                    monitored.label.as_deref().unwrap_or_default(),
+                    status.rssi,
+                    status.locked,
+                    status.device_auto_unlock_enabled
+                ),
+                Err(err) => println!("Failed to get Apple Watch status: {err}"),
+            }
+        }
+
+        false
+    }
+
+    /// Runs the lock command, e.g. `loginctl lock-session`, passing
+    /// it the ID of the session to lock if there is one.
+    fn lock(lock_command: &str, session: Option<&String>) {
+        let mut lock_command = lock_command.split_whitespace();
+        let Some(program) = lock_command.next() else {
+            return;
+        };
+
+        match std::process::Command::new(program)
+            .args(lock_command)
+            .args(session)
+            .status()
+        {
+            Ok(status) if status.success() => println!("Locked the session"),
+            Ok(status) => eprintln!("Lock command failed: {status}"),
+            Err(err) => eprintln!("Failed to run lock command: {err}"),
+        }
+    }
+
+    /// Reads the user's config entries, one per line, from standard
+    /// input, as written by the PAM module.
+    fn read_entries() -> Result<Vec<Entry>, String> {
+        let mut entries = Vec::new();
+        for (line_number, line) in std::io::stdin().lines().enumerate() {
+            let line = line.map_err(|err| err.to_string())?;
+            entries.push(Entry::try_from((line_number, &line)).map_err(|err| err.to_string())?);
+        }
+
+        Ok(entries)
+    }
+}
+
+#[async_trait(?Send)]
+impl CommandDelegate for MonitorCommand {
+    fn name(&self) -> &'static str {
+        "monitor"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Monitors the presence of a user's Apple Watch and locks the session when it leaves, started by the PAM module's `monitor` argument")
+            .arg(
+                Arg::new("user")
+                    .required(true)
+                    .help("Specifies the user whose Apple Watch is monitored"),
+            )
+            .arg(
+                Arg::new("interval")
+                    .long("interval")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .default_value("10000")
+                    .help("Specifies how long, in milliseconds, to wait between checks for the Apple Watch"),
+            )
+            .arg(
+                Arg::new("misses")
+                    .long("misses")
+                    .value_parser(value_parser!(u8).range(1..))
+                    .default_value("2")
+                    .help("Specifies how many checks in a row must miss the Apple Watch before locking"),
+            )
+            .arg(
+                Arg::new("unlock-threshold")
+                    .long("unlock-threshold")
+                    .value_parser(value_parser!(i16))
+                    .default_value("-80")
+                    .allow_negative_numbers(true)
+                    .help("Specifies the RSSI the Apple Watch must be above to be present, unless set in the user's config entry"),
+            )
+            .arg(
+                Arg::new("adapter")
+                    .long("adapter")
+                    .help("Specifies the Bluetooth adapter, e.g. hci0, unless set in the user's config entry (default: system default)"),
+            )
+            .arg(
+                Arg::new("require-auto-unlock")
+                    .long("require-auto-unlock")
+                    .value_parser(value_parser!(bool))
+                    .default_value("true")
+                    .help("Specifies if the Apple Watch must have auto-unlock enabled to be present, unless set in the user's config entry"),
+            )
+            .arg(
+                Arg::new("lock-command")
+                    .long("lock-command")
+                    .default_value("loginctl lock-session")
+                    .help("Specifies the command that locks the session, the --session ID is appended to it"),
+            )
+            .arg(
+                Arg::new("session")
+                    .long("session")
+                    .help("Specifies the ID of the login session to lock (default: the session of the lock command)"),
+            )
+            .arg(
+                Arg::new("entries-from-stdin")
+                    .long("entries-from-stdin")
+                    .action(ArgAction::SetTrue)
+                    .help("Reads the user's config entries from standard input, as the PAM module runs the monitor as the user, who can't read the config"),
+            )
+            .args(search_args())
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let user: &String = args.get_one("user").expect("required argument");
+        let interval =
+            Duration::from_millis(*args.get_one::<u64>("interval").expect("has default"));
+        let max_misses: &u8 = args.get_one("misses").expect("has default");
+        let unlock_threshold: &i16 = args.get_one("unlock-threshold").expect("has default");
+        let adapter: Option<&String> = args.get_one("adapter");
+        let require_auto_unlock: &bool = args.get_one("require-auto-unlock").expect("has default");
+        let retries: &u8 = args.get_one("retries").expect("has default");
+        let retry_timeout: &u64 = args.get_one("retry-timeout").expect("has default");
+        let lock_command: &String = args.get_one("lock-command").expect("has default");
+        let session_id: Option<&String> = args.get_one("session");
+
+        // The entries written by the PAM module are already the user's,
+        // so only those loaded from the config are matched to the user.
+        let (config, stdin_entries);
+        let entries: Vec<&Entry> = if args.get_flag("entries-from-stdin") {
+            stdin_entries = match Self::read_entries() {
+                Ok(entries) => entries,
+                Err(err) => {
+                    eprintln!("Failed to read config entries: {err}");
+                    return 1;
+                }
+            };
+
+            stdin_entries.iter().collect()
+        } else {
+            println!("Loading configuration for Apple Watch PAM module");
+            config = match load_config(args) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("Failed to load configuration: {err}");
+                    return 1;
+                }
+            };
+
+            config.get_user_entries(user)
+        };
+
+        println!("Creating Bluetooth session");
+        let session = match bluer::Session::new().await {
+            Ok(session) => session,
+            Err(err) => {
+                eprintln!("Failed to create Bluetooth session: {err}");
+                return 1;
+            }
+        };
+
+        let now = SystemTime::now();
+        let mut watches = Vec::new();
+        for entry in entries {
+            if !entry.options.is_active(now) {
+                continue;
+            }
+
+            let irk = match entry.irk() {
+                Ok(irk) => irk,
+                Err(err) => {
+                    eprintln!("Failed to decode IRK: {err}");
+                    return 1;
+                }
+            };
+
+            // The per-user values from the config entry are
+            // preferred over the arguments.
+            let selected = match entry.options.adapter().or_else(|| adapter.cloned()) {
+                Some(name) => session.adapter(&name),
+                None => session.default_adapter().await,
+            };
+
+            let selected = match selected {
+                Ok(selected) => selected,
+                Err(err) => {
+                    eprintln!("Failed to obtain access to Bluetooth adapter: {err}");
+                    return 1;
+                }
+            };
+
+            watches.push(MonitoredWatch {
+                label: entry.options.label(),
+                irk,
+                adapter: selected,
+                unlock_threshold: entry
+                    .options
+                    .unlock_threshold()
+                    .unwrap_or(*unlock_threshold),
+                require_auto_unlock: entry
+                    .options
+                    .require_auto_unlock()
+                    .unwrap_or(*require_auto_unlock),
+                retries: entry.options.retries().unwrap_or(*retries),
+                retry_timeout: entry.options.retry_timeout().unwrap_or(*retry_timeout),
+            });
+        }
+
+        if watches.is_empty() {
+            eprintln!("No enabled Apple Watch is configured for '{user}'");
+            return 1;
+        }
+
+        println!("Monitoring Apple Watch presence for '{user}'");
+        let mut misses: u8 = 0;
+        loop {
+            if Self::is_present(&watches, args).await {
+                misses = 0;
+            } else {
+                misses = misses.saturating_add(1);
+
+                // The session is only locked once each time the watch
+                // leaves, rather than on every check that misses it.
+                if misses == *max_misses {
+                    println!("Apple Watch has left, locking the session");
+                    Self::lock(lock_command, session_id);
+                }
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
@@ -1,15 +1,124 @@
 use crate::cmds::CommandDelegate;
+use crate::lib::args::ModuleArgs;
 
 use async_trait::async_trait;
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use pam::{Client, Conversation};
 use std::ffi::{CStr, CString};
+use std::path::Path;
 use std::str::FromStr;
 
 pub struct PAMTestCommand;
 
 impl PAMTestCommand {
     const PAM_MODULE_NAME: &'static str = "apple-watch";
+
+    /// Specifies the file name of the Apple Watch PAM module as
+    /// it is written in a service policy.
+    const PAM_MODULE_FILE: &'static str = "pam_apple_watch.so";
+
+    /// Specifies the directory holding the PAM service policies.
+    const PAM_POLICY_DIR: &'static str = "/etc/pam.d";
+
+    /// Splits a line of a PAM service policy into its fields, keeping
+    /// bracketed fields, e.g. `[success=1 default=ignore]`, whole.
+    fn policy_fields(line: &str) -> Vec<&str> {
+        let mut fields = Vec::new();
+        let mut rest = line.trim();
+
+        while !rest.is_empty() {
+            let end = if rest.starts_with('[') {
+                rest.find(']').map_or(rest.len(), |end| end + 1)
+            } else {
+                rest.find(char::is_whitespace).unwrap_or(rest.len())
+            };
+
+            fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+
+        fields
+    }
+
+    /// Validates the module arguments of a line of a PAM service policy,
+    /// returning `None` if the line doesn't use the Apple Watch PAM module
+    /// or otherwise the problems found and if the `lenient` argument is set.
+    fn validate_line(line: &str) -> Option<(Vec<String>, bool)> {
+        let line = line.split('#').next().unwrap_or_default();
+        let fields = Self::policy_fields(line);
+
+        // Fields are the type, the control, the module and its arguments
+        let module = fields.get(2)?;
+        if Path::new(module).file_name()? != Self::PAM_MODULE_FILE {
+            return None;
+        }
+
+        let args = fields[3..].iter().map(|arg| {
+            arg.strip_prefix('[')
+                .and_then(|arg| arg.strip_suffix(']'))
+                .unwrap_or(arg)
+        });
+
+        let (args, errors) = ModuleArgs::parse(args);
+        Some((
+            errors.iter().map(ToString::to_string).collect(),
+            args.lenient,
+        ))
+    }
+
+    /// Validates the module arguments of every line of the service policy
+    /// that uses the Apple Watch PAM module, or only the supplied line.
+    fn validate(service: &str, line: Option<&String>) -> i32 {
+        let lines: Vec<(usize, String)> = if let Some(line) = line {
+            vec![(1, line.clone())]
+        } else {
+            let path = Path::new(Self::PAM_POLICY_DIR).join(service);
+            println!("Validating PAM service policy {}", path.display());
+
+            match std::fs::read_to_string(&path) {
+                Ok(policy) => policy
+                    .lines()
+                    .map(ToString::to_string)
+                    .enumerate()
+                    .map(|(i, line)| (i + 1, line))
+                    .collect(),
+                Err(err) => {
+                    eprintln!("Failed to read PAM service policy: {err}");
+                    return 1;
+                }
+            }
+        };
+
+        let mut found = false;
+        let mut status_code = 0;
+        for (line_number, line) in lines {
+            let Some((errors, lenient)) = Self::validate_line(&line) else {
+                continue;
+            };
+
+            found = true;
+            for err in errors {
+                if lenient {
+                    println!("WARN: line {line_number}: {err}, ignored as `lenient` is set");
+                    status_code = status_code.max(2);
+                } else {
+                    eprintln!("ERROR: line {line_number}: {err}");
+                    status_code = 1;
+                }
+            }
+        }
+
+        if !found {
+            eprintln!("No line uses {}", Self::PAM_MODULE_FILE);
+            return 1;
+        }
+
+        if status_code == 0 {
+            println!("The module arguments are valid");
+        }
+
+        status_code
+    }
 }
 
 #[async_trait(?Send)]
@@ -33,12 +142,28 @@ impl CommandDelegate for PAMTestCommand {
                         "Specifies the name of the PAM service policy configuration in /etc/pam.d",
                     ),
             )
+            .arg(
+                Arg::new("validate")
+                    .long("validate")
+                    .action(ArgAction::SetTrue)
+                    .help("Validates the module arguments in the service policy instead of authenticating"),
+            )
+            .arg(
+                Arg::new("line")
+                    .long("line")
+                    .requires("validate")
+                    .help("Specifies a single service policy line to validate, e.g. 'auth sufficient pam_apple_watch.so debug'"),
+            )
     }
 
     async fn execute(&self, args: &ArgMatches) -> i32 {
         let user: &String = args.get_one("user").expect("required argument");
         let service: &String = args.get_one("service-name").expect("has default");
 
+        if args.get_flag("validate") {
+            return Self::validate(service, args.get_one("line"));
+        }
+
         println!("Connecting to Apple Watch PAM module [{service}]");
         let mut client = match Client::with_conversation(
             service,
@@ -78,8 +203,16 @@ impl Conversation for MiscConv {
         Ok(CString::from_str(self.user.as_str()).unwrap())
     }
 
-    fn prompt_blind(&mut self, _: &CStr) -> Result<CString, ()> {
-        todo!()
+    /// Refuses to answer password prompts, the Apple Watch module never
+    /// asks for one, the `pam` crate reports the refusal to the module
+    /// as a `PAM_CONV_ERR` conversation error.
+    fn prompt_blind(&mut self, msg: &CStr) -> Result<CString, ()> {
+        eprintln!(
+            "[{}] ERROR: Not answering password prompt: {}",
+            self.mod_name,
+            msg.to_string_lossy()
+        );
+        Err(())
     }
 
     fn info(&mut self, msg: &CStr) {
@@ -90,3 +223,21 @@ impl Conversation for MiscConv {
         eprintln!("[{}] ERROR: {}", self.mod_name, msg.to_str().unwrap());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::MiscConv;
+
+    use pam::Conversation;
+
+    #[test]
+    fn password_prompts_are_refused() {
+        let mut conv = MiscConv {
+            mod_name: "apple-watch".to_string(),
+            user: "alice".to_string(),
+        };
+
+        assert!(conv.prompt_blind(c"Password: ").is_err());
+        assert_eq!(conv.prompt_echo(c"login: ").unwrap().as_c_str(), c"alice");
+    }
+}
@@ -1,15 +1,170 @@
-use crate::cmds::CommandDelegate;
+use crate::cmds::{CommandDelegate, find_watch, search_args};
+use crate::lib::capture::Capture;
+use crate::lib::decision::{DenialReason, UnlockDecision};
+use crate::lib::irk::Irk;
+use crate::lib::rssi::RssiFilter;
+use crate::lib::scanner::Scanner;
 use crate::lib::watch::AppleWatch;
 
 use async_trait::async_trait;
-use base64::engine::general_purpose::STANDARD;
-use base64::Engine;
-use clap::{Arg, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use std::cell::RefCell;
+use std::path::PathBuf;
 use std::process::exit;
 use std::time::Duration;
+use tokio::time::timeout;
 
 pub struct QueryStatusCommand;
 
+impl QueryStatusCommand {
+    async fn query_status<S: Scanner>(adapter: &S, irk: Irk, args: &ArgMatches) -> i32 {
+        let rssi_samples: &u8 = args.get_one("rssi-samples").expect("has default");
+        let rssi_window: &u64 = args.get_one("rssi-window").expect("has default");
+        let rssi_filter: &RssiFilter = args.get_one("rssi-filter").expect("has default");
+
+        let mut watch = AppleWatch::new(irk);
+
+        println!("Searching for Apple Watch");
+        match find_watch(&mut watch, adapter, args).await {
+            Err(err) => {
+                println!("Failed to find Apple Watch: {err}");
+                return 1;
+            }
+            Ok(tries) => println!("Found Apple Watch after {tries} tries"),
+        }
+
+        let status = match watch.get_watch_status().await {
+            Ok(status) => status,
+            Err(err) => {
+                println!("Failed to get Apple Watch status: {err}");
+                return 1;
+            }
+        };
+
+        let mut filtered_rssi = None;
+        if *rssi_samples > 1 {
+            println!("Sampling Apple Watch RSSI");
+            match watch
+                .sample_rssi(adapter, *rssi_samples, Duration::from_millis(*rssi_window))
+                .await
+            {
+                Err(err) => {
+                    println!("Failed to sample Apple Watch RSSI: {err}");
+                    return 1;
+                }
+                Ok(samples) => {
+                    println!("Collected {} RSSI samples: {samples:?}", samples.len());
+                    filtered_rssi = rssi_filter.apply(&samples);
+                }
+            }
+        }
+
+        println!("Apple Watch Status");
+        println!(
+            "\tAddress.......................: {}",
+            watch.get_watch_address()
+        );
+        println!("\tRSSI..........................: {}", status.rssi);
+        if let Ok(Some(tx_power)) = watch.get_watch_tx_power().await {
+            println!("\tTX power......................: {tx_power}");
+        }
+        if let Some(filtered_rssi) = filtered_rssi {
+            println!("\tFiltered RSSI.................: {filtered_rssi} ({rssi_filter})");
+        }
+        println!("\tLocked........................: {}", status.locked);
+        println!(
+            "\tAuto-unlock devices enabled...: {}",
+            status.device_auto_unlock_enabled
+        );
+        println!(
+            "\tAction........................: {}",
+            status.nearby_info.action_code
+        );
+        println!(
+            "\tStatus flags..................: {:#x}",
+            status.nearby_info.status_flags
+        );
+        println!(
+            "\tData flags....................: {:#x}",
+            status.nearby_info.data_flags
+        );
+        println!(
+            "\tWi-Fi on......................: {}",
+            status.nearby_info.wifi_on()
+        );
+        println!(
+            "\tOS version....................: {:#x}",
+            status.nearby_info.os_version()
+        );
+        println!(
+            "\tAuthentication tag............: {:02x?}",
+            status.nearby_info.authentication_tag
+        );
+
+        for message_type in &status.other_messages {
+            println!("\tAlso advertising..............: {message_type}");
+        }
+
+        0
+    }
+
+    /// Replays the capture through the same checks as the PAM module,
+    /// returning the decision it would have made.
+    ///
+    /// The replay runs on paused time, which only moves on once every
+    /// search and sample is waiting, so each replay of a capture makes
+    /// the same decision however busy the system is.
+    async fn replay_decision<S: Scanner>(
+        adapter: &S,
+        irk: Irk,
+        args: &ArgMatches,
+    ) -> UnlockDecision {
+        let rssi_samples: &u8 = args.get_one("rssi-samples").expect("has default");
+        let rssi_window: &u64 = args.get_one("rssi-window").expect("has default");
+        let rssi_filter: &RssiFilter = args.get_one("rssi-filter").expect("has default");
+        let unlock_threshold: &i16 = args.get_one("unlock-threshold").expect("has default");
+        let require_auto_unlock: &bool = args.get_one("require-auto-unlock").expect("has default");
+        let retries: &u8 = args.get_one("retries").expect("has default");
+        let retry_timeout: &u64 = args.get_one("retry-timeout").expect("has default");
+        let max_wait: Option<&u64> = args.get_one("max-wait");
+
+        tokio::time::pause();
+
+        let decision = RefCell::new(UnlockDecision::new(
+            "replay",
+            None,
+            *rssi_filter,
+            *unlock_threshold,
+            *require_auto_unlock,
+        ));
+
+        println!("Replaying checks of the PAM module");
+        let check = UnlockDecision::check(
+            &decision,
+            adapter,
+            irk,
+            *retries,
+            Duration::from_millis(*retry_timeout),
+            *rssi_samples,
+            Duration::from_millis(*rssi_window),
+        );
+        let result = match max_wait {
+            Some(max_wait) => timeout(Duration::from_millis(*max_wait), check)
+                .await
+                .unwrap_or_else(|_| {
+                    decision.borrow_mut().finish(Some(DenialReason::Timeout));
+                    Ok(())
+                }),
+            None => check.await,
+        };
+        if let Err(err) = result {
+            println!("Failed to check Apple Watch: {err}");
+        }
+
+        decision.into_inner()
+    }
+}
+
 #[async_trait(?Send)]
 impl CommandDelegate for QueryStatusCommand {
     fn name(&self) -> &'static str {
@@ -22,28 +177,86 @@ impl CommandDelegate for QueryStatusCommand {
             .arg(
                 Arg::new("irk")
                     .required(true)
-                    .help("Identity Resolution Key, in base64, of the Apple Watch to query"),
+                    .help("Identity Resolution Key, in Base64 or hex, of the Apple Watch to query"),
             )
+            .arg(
+                Arg::new("replay")
+                    .long("replay")
+                    .value_parser(value_parser!(PathBuf))
+                    .help("Replays a capture file, recorded by `capture`, instead of using the Bluetooth adapter"),
+            )
+            .arg(
+                Arg::new("rssi-samples")
+                    .long("rssi-samples")
+                    .value_parser(value_parser!(u8))
+                    .default_value("1")
+                    .help("Specifies how many RSSI samples to collect and filter"),
+            )
+            .arg(
+                Arg::new("rssi-window")
+                    .long("rssi-window")
+                    .value_parser(value_parser!(u64))
+                    .default_value("1000")
+                    .help("Specifies the maximum time, in milliseconds, spent collecting RSSI samples"),
+            )
+            .arg(
+                Arg::new("rssi-filter")
+                    .long("rssi-filter")
+                    .value_parser(value_parser!(RssiFilter))
+                    .default_value("median")
+                    .help("Specifies how RSSI samples are combined (median, trimmed_mean, ema, kalman)"),
+            )
+            .arg(
+                Arg::new("unlock-threshold")
+                    .long("unlock-threshold")
+                    .value_parser(value_parser!(i16))
+                    .default_value("-80")
+                    .allow_negative_numbers(true)
+                    .requires("replay")
+                    .help("Specifies the RSSI the Apple Watch must be above to unlock, when replaying a capture"),
+            )
+            .arg(
+                Arg::new("require-auto-unlock")
+                    .long("require-auto-unlock")
+                    .value_parser(value_parser!(bool))
+                    .default_value("true")
+                    .requires("replay")
+                    .help("Specifies if the Apple Watch must have auto-unlock enabled to unlock, when replaying a capture"),
+            )
+            .args(search_args())
     }
 
     async fn execute(&self, args: &ArgMatches) -> i32 {
-        let encoded_irl: &String = args.get_one("irk").expect("required argument");
+        let encoded_irk: &String = args.get_one("irk").expect("required argument");
 
         println!("Decoding Identity Resolution Key for Apple Watch");
-        let mut raw_irk: [u8; 16] = [0; 16];
-        match STANDARD.decode_slice(encoded_irl, &mut raw_irk[..]) {
+        let raw_irk: Irk = match encoded_irk.parse() {
+            Ok(raw_irk) => raw_irk,
             Err(err) => {
                 eprintln!("Failed to decode IRK: {err}");
                 exit(1)
             }
-            Ok(decoded_length) if decoded_length != 16 => {
-                eprintln!("Corrupt IRK, it must be 16 bytes long");
-                exit(1)
+        };
+
+        if let Some(capture_path) = args.get_one::<PathBuf>("replay") {
+            println!("Loading capture {}", capture_path.display());
+            let capture = match Capture::load(capture_path) {
+                Ok(capture) => capture,
+                Err(err) => {
+                    println!("Failed to load capture: {err}");
+                    return 1;
+                }
+            };
+
+            let decision = Self::replay_decision(&capture.into_scanner(), raw_irk, args).await;
+            match decision.denial {
+                Some(denial) => println!("Apple Watch {}", denial.message()),
+                None => println!("Apple Watch can unlock"),
             }
-            Ok(_) => raw_irk.reverse(),
-        }
+            println!("{}", decision.to_json());
 
-        let mut watch = AppleWatch::new(raw_irk);
+            return i32::from(!decision.unlocked);
+        }
 
         println!("Creating Bluetooth session");
         let session = match bluer::Session::new().await {
@@ -63,38 +276,106 @@ impl CommandDelegate for QueryStatusCommand {
             }
         };
 
-        println!("Searching for Apple Watch");
-        match watch
-            .find_watch(&adapter, 3, Duration::from_millis(500))
-            .await
-        {
-            Err(err) => {
-                println!("Failed to find Apple Watch: {err}");
-                return 1;
-            }
-            Ok(tries) => println!("Found Apple Watch after {tries} tries"),
-        }
+        Self::query_status(&adapter, raw_irk, args).await
+    }
+}
 
-        let status = match watch.get_watch_status().await {
-            Ok(status) => status,
-            Err(err) => {
-                println!("Failed to get Apple Watch status: {err}");
-                return 1;
-            }
+#[cfg(test)]
+mod tests {
+    use super::QueryStatusCommand;
+    use crate::cmds::CommandDelegate;
+    use crate::lib::capture::Capture;
+    use crate::lib::decision::{DenialReason, UnlockDecision};
+
+    /// Specifies an IRK and an address that resolves with it.
+    const WATCH_IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+    const WATCH_ADDRESS: &str = "4A:11:22:95:3D:A2";
+
+    /// Specifies the Nearby Info message of an unlocked watch
+    /// with auto-unlock enabled.
+    const UNLOCKED: &str = "004c=10050180aabbcc";
+
+    /// Replays the capture records, as written by `capture`,
+    /// with the arguments, returning the decision.
+    fn replay(records: &[String], args: &[&str]) -> UnlockDecision {
+        let args = QueryStatusCommand
+            .definition()
+            .try_get_matches_from(
+                ["query_status", WATCH_IRK, "--replay", "capture"]
+                    .iter()
+                    .chain(args),
+            )
+            .unwrap();
+        let capture = Capture {
+            records: records
+                .iter()
+                .map(|record| record.parse().unwrap())
+                .collect(),
         };
 
-        println!("Apple Watch Status");
-        println!(
-            "\tAddress.......................: {}",
-            watch.get_watch_address()
-        );
-        println!("\tRSSI..........................: {}", status.rssi);
-        println!("\tLocked........................: {}", status.locked);
-        println!(
-            "\tAuto-unlock devices enabled...: {}",
-            status.device_auto_unlock_enabled
-        );
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(QueryStatusCommand::replay_decision(
+                &capture.into_scanner(),
+                WATCH_IRK.parse().unwrap(),
+                &args,
+            ))
+    }
 
-        0
+    #[test]
+    fn replay_makes_the_same_decision_every_time() {
+        let records = [
+            format!("1000;{WATCH_ADDRESS};-60;;{UNLOCKED}"),
+            format!("1400;{WATCH_ADDRESS};-70;;{UNLOCKED}"),
+            format!("1800;{WATCH_ADDRESS};-65;;{UNLOCKED}"),
+        ];
+        let args = ["--rssi-samples", "3", "--rssi-window", "1000"];
+
+        let decision = replay(&records, &args);
+        assert!(decision.unlocked);
+        assert_eq!(decision.rssi_samples, vec![-60, -70, -65]);
+        assert_eq!(decision.rssi, Some(-65));
+
+        // The replay follows the recorded timing rather than the clock
+        let replayed = replay(&records, &args);
+        assert_eq!(replayed.rssi_samples, decision.rssi_samples);
+        assert_eq!(replayed.elapsed_ms, decision.elapsed_ms);
+        assert!((800..810).contains(&decision.elapsed_ms));
+    }
+
+    #[test]
+    fn replay_uses_the_unlock_threshold() {
+        let records = [format!("1000;{WATCH_ADDRESS};-60;;{UNLOCKED}")];
+
+        let decision = replay(&records, &["--unlock-threshold", "-50"]);
+        assert_eq!(decision.denial, Some(DenialReason::TooFarAway));
+        assert!(!decision.unlocked);
+    }
+
+    #[test]
+    fn watch_recorded_after_the_searches_is_not_found() {
+        let records = [
+            "1000;00:11:22:33:44:55;-60;;".to_string(),
+            format!("3000;{WATCH_ADDRESS};-60;;{UNLOCKED}"),
+        ];
+
+        let decision = replay(&records, &["--retries", "3", "--retry-timeout", "500"]);
+        assert!(!decision.found);
+        assert_eq!(decision.denial, Some(DenialReason::NotFound));
+        assert!((1500..1510).contains(&decision.elapsed_ms));
+    }
+
+    #[test]
+    fn replay_stops_at_the_maximum_wait() {
+        let records = [
+            "1000;00:11:22:33:44:55;-60;;".to_string(),
+            format!("3000;{WATCH_ADDRESS};-60;;{UNLOCKED}"),
+        ];
+
+        let decision = replay(&records, &["--retry-timeout", "5000", "--max-wait", "1000"]);
+        assert_eq!(decision.denial, Some(DenialReason::Timeout));
+        assert!((1000..1010).contains(&decision.elapsed_ms));
     }
 }
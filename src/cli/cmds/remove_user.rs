@@ -0,0 +1,73 @@
+use crate::cmds::{CommandDelegate, load_config};
+
+use async_trait::async_trait;
+use clap::{Arg, ArgMatches, Command};
+
+pub struct RemoveUserCommand;
+
+#[async_trait(?Send)]
+impl CommandDelegate for RemoveUserCommand {
+    fn name(&self) -> &'static str {
+        "remove_user"
+    }
+
+    fn definition(&self) -> Command {
+        Command::new(self.name())
+            .about("Removes a user from the Apple Watch PAM module configuration")
+            .long_about(concat!(
+                "Updates the Apple Watch PAM module config, /etc/security/apple_watch.conf,\n",
+                "to remove the entries for a user, by default every watch registered to the user\n",
+                "is removed, use --label or --irk to remove a single watch.\n",
+                "\n",
+                "This command requires root permission (i.e. sudo) to modify the configuration file."
+            ))
+            .arg(
+                Arg::new("user")
+                    .required(true)
+                    .help("Specifies the user, group or wildcard entry to remove"),
+            )
+            .arg(
+                Arg::new("label")
+                    .long("label")
+                    .help("Specifies the label of the watch to remove"),
+            )
+            .arg(
+                Arg::new("irk")
+                    .long("irk")
+                    .help("Specifies the Base64 encoded Identity Resolution Key of the watch to remove"),
+            )
+    }
+
+    async fn execute(&self, args: &ArgMatches) -> i32 {
+        let user: &String = args.get_one("user").expect("required argument");
+        let label: Option<&str> = args.get_one::<String>("label").map(String::as_str);
+        let irk: Option<&str> = args.get_one::<String>("irk").map(String::as_str);
+
+        println!("Loading configuration for Apple Watch PAM module");
+        let mut config = match load_config(args) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("Failed to load configuration: {err}");
+                return 1;
+            }
+        };
+
+        println!("Removing user '{user}' from PAM module configuration");
+        match config.remove_user(user, label, irk) {
+            0 => {
+                eprintln!("No matching config entry for '{user}'");
+                return 1;
+            }
+            removed => println!("Removed {removed} entries"),
+        }
+
+        println!("Saving configuration");
+        if let Err(err) = config.save() {
+            eprintln!("Failed to save configuration: {err}");
+            return 1;
+        }
+
+        println!("Configuration saved successfully");
+        0
+    }
+}
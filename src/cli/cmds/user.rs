@@ -1,8 +1,9 @@
-use crate::cmds::CommandDelegate;
-use crate::lib::conf::Config;
+use crate::cmds::{CommandDelegate, load_config};
+use crate::lib::conf::Entry;
+use crate::lib::irk::Irk;
 
 use async_trait::async_trait;
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 pub struct UserCommand;
 
@@ -19,6 +20,9 @@ impl CommandDelegate for UserCommand {
                 "Updates the Apple Watch PAM module config, /etc/security/apple_watch.conf,\n",
                 "to either add a new user mapping, or update an existing mapping if one exist.\n",
                 "\n",
+                "A user may register several Apple Watches by giving each one a distinct --label,\n",
+                "the user is unlocked when any one of their watches passes the checks.\n",
+                "\n",
                 "This command requires root permission (i.e. sudo) to modify the configuration file."
             ))
             .arg(
@@ -26,17 +30,52 @@ impl CommandDelegate for UserCommand {
                     .required(true)
                     .help("Specifies the user to create, or update, a configuration mapping for"),
             )
-            .arg(Arg::new("irk").required(true).help(
-                "Specifies the Base64 encoded Identity Resolution Key for the user's Apple Watch",
-            ))
+            .arg(Arg::new("irk").required(true).help(concat!(
+                "Specifies the Identity Resolution Key for the user's Apple Watch, in Base64 or hex, ",
+                "optionally prefixed with its byte order (lsb: or msb:)"
+            )))
+            .arg(
+                Arg::new("force")
+                    .long("force")
+                    .action(ArgAction::SetTrue)
+                    .help("Saves the IRK as written even if it isn't a valid 16 byte key"),
+            )
+            .arg(
+                Arg::new("label")
+                    .long("label")
+                    .help("Specifies a name that distinguishes this watch from the user's other watches"),
+            )
     }
 
     async fn execute(&self, args: &ArgMatches) -> i32 {
         let user: &String = args.get_one("user").expect("required argument");
         let irk: &String = args.get_one("irk").expect("required argument");
+        let label: Option<&str> = args.get_one::<String>("label").map(String::as_str);
+
+        // Valid keys are saved in the canonical Base64 form, whatever
+        // encoding and byte order they were supplied in.
+        let irk: String = match irk.parse::<Irk>() {
+            Ok(parsed_irk) => parsed_irk.to_base64(),
+            Err(err) if args.get_flag("force") => {
+                println!(
+                    "WARN: Saving invalid IRK, the PAM module will skip the entry until it is corrected with `add_user` or `remove_user`: {err}"
+                );
+                irk.clone()
+            }
+            Err(err) => {
+                eprintln!("Invalid IRK for '{user}': {err}");
+                eprintln!("Re-run with --force to save it anyway");
+                return 1;
+            }
+        };
+
+        if let Err(err) = Entry::check_fields(user, &irk, label) {
+            eprintln!("{err}");
+            return 1;
+        }
 
         println!("Loading configuration for Apple Watch PAM module");
-        let mut config = match Config::load() {
+        let mut config = match load_config(args) {
             Ok(config) => config,
             Err(err) => {
                 eprintln!("Failed to load configuration: {err}");
@@ -45,8 +84,13 @@ impl CommandDelegate for UserCommand {
         };
 
         println!("Adding user '{user}' to PAM module configuration");
-        if config.update_user(user, irk) {
-            println!("WARN: User already existed, updating existing entry");
+        match config.update_user(user, &irk, label) {
+            Ok(true) => println!("WARN: User already existed, updating existing entry"),
+            Ok(false) => {}
+            Err(err) => {
+                eprintln!("Failed to add user: {err}");
+                return 1;
+            }
         }
 
         println!("Saving configuration");
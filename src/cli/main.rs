@@ -2,8 +2,11 @@ mod cmds;
 #[path = "../lib.rs"]
 mod lib;
 
-use clap::Command;
+use crate::lib::conf::Config;
+
+use clap::{Arg, Command, value_parser};
 use std::env;
+use std::path::PathBuf;
 use std::process::exit;
 
 #[tokio::main(flavor = "current_thread")]
@@ -14,7 +17,16 @@ async fn main() {
         .map(|delegate| delegate.definition())
         .collect();
 
-    let mut cmd = Command::new(env!("CARGO_CRATE_NAME")).subcommands(commands);
+    let mut cmd = Command::new(env!("CARGO_CRATE_NAME"))
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .global(true)
+                .value_parser(value_parser!(PathBuf))
+                .default_value(Config::DEFAULT_LOCATION)
+                .help("Specifies the location of the Apple Watch PAM module config"),
+        )
+        .subcommands(commands);
     let matches = cmd.get_matches_mut();
     let Some((cmd_name, args)) = matches.subcommand() else {
         let _ = cmd.print_help();
@@ -1,5 +1,19 @@
-use crate::lib::conf::ConfigError::InvalidEntryCount;
+#[cfg(feature = "cli")]
+pub mod check;
+
+use crate::lib::conf::ConfigError::{
+    DuplicateEntry, InsecurePermissions, InvalidDropIn, InvalidEntryCount, InvalidEntryIrk,
+    InvalidEntryOption, MissingEntryUser, UnknownEntryOption,
+};
+use crate::lib::irk::{Irk, IrkError};
+use crate::lib::nss;
 use std::fmt::Display;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use thiserror::Error;
 
@@ -7,42 +21,274 @@ use thiserror::Error;
 pub struct Config {
     pub entries: Vec<Entry>,
 
+    /// Specifies the entries that couldn't be loaded, they are skipped
+    /// so that a bad entry only affects the user it belongs to.
+    pub skipped: Vec<SkippedEntry>,
+
+    /// Specifies the location of the config that changes are saved
+    /// to, entries from drop-in configs are never modified.
+    #[cfg(feature = "cli")]
+    path: PathBuf,
+
     #[cfg(feature = "cli")]
     lines: Vec<String>,
+
+    /// Specifies the contents of the config when it was loaded, used
+    /// to detect modifications made by another process before saving.
+    #[cfg(feature = "cli")]
+    loaded: String,
 }
 
 impl Config {
-    const CONF_LOCATION: &'static str = "/etc/security/apple_watch.conf";
+    pub const DEFAULT_LOCATION: &'static str = "/etc/security/apple_watch.conf";
+
+    /// Specifies the extension of the drop-in configs that are merged
+    /// into the config, the drop-ins are found in the directory named
+    /// after the config with a `.d` extension (e.g. `apple_watch.d`).
+    const DROP_IN_EXTENSION: &'static str = "conf";
+
+    /// Specifies the mode of a newly created config, the config
+    /// contains IRKs so it must only be readable by root.
+    #[cfg(feature = "cli")]
+    const CONF_MODE: u32 = 0o600;
+
+    /// Loads the config at the supplied path and then merges in the
+    /// entries of its drop-in configs, in lexical order.
+    ///
+    /// Entries that are invalid are added to [`Config::skipped`] rather
+    /// than failing the load, only a config that can't be read, or has
+    /// insecure permissions, is an error.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let mut skipped = Vec::new();
 
-    pub fn load() -> Result<Self, ConfigError> {
-        let raw_conf = std::fs::read_to_string(Self::CONF_LOCATION)?;
-        let lines: Vec<String> = raw_conf.lines().map(ToString::to_string).collect();
+        // Only the CLI keeps the config as loaded, to save it later
+        #[cfg_attr(not(feature = "cli"), expect(unused_variables))]
+        let (raw_conf, mut entries) = Self::load_file(path, &mut skipped)?;
 
-        let entries: Vec<Entry> = lines
+        for drop_in in Self::drop_ins(path)? {
+            let (_, drop_in_entries) = Self::load_file(&drop_in, &mut skipped)
+                .map_err(|err| InvalidDropIn(drop_in.clone(), Box::new(err)))?;
+
+            entries.extend(drop_in_entries.into_iter().map(|entry| Entry {
+                drop_in: Some(drop_in.clone()),
+                ..entry
+            }));
+        }
+
+        // Duplicates are found across the config and its drop-ins, as a
+        // drop-in repeating an entry is just as ambiguous.
+        let located: Vec<(&Path, &Entry)> = entries
             .iter()
-            .enumerate()
-            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
-            .map(TryFrom::try_from)
-            .collect::<Result<Vec<Entry>, ConfigError>>()?;
+            .map(|entry| (entry.drop_in.as_deref().unwrap_or(path), entry))
+            .collect();
+        let duplicates = Self::duplicate_entries(&located);
+
+        for (i, err) in duplicates.into_iter().rev() {
+            let entry = entries.remove(i);
+            let entry_path = entry.drop_in.as_deref().unwrap_or(path);
+            skipped.push(SkippedEntry::new(
+                entry_path,
+                entry.line_number,
+                &entry.user,
+                err,
+            ));
+        }
 
         Ok(Self {
             entries,
+            skipped,
+
+            #[cfg(feature = "cli")]
+            path: path.into(),
+
+            #[cfg(feature = "cli")]
+            lines: raw_conf.lines().map(ToString::to_string).collect(),
 
             #[cfg(feature = "cli")]
-            lines,
+            loaded: raw_conf,
         })
     }
 
-    #[cfg_attr(feature = "cli", allow(unused))]
-    pub fn get_user(&self, user: &String) -> Option<&Entry> {
-        self.entries.iter().find(|entry| entry.user == *user)
+    /// Reads and parses a single config, returning its raw contents
+    /// along with the entries it contains, the invalid entries are
+    /// added to `skipped`.
+    fn load_file(
+        path: &Path,
+        skipped: &mut Vec<SkippedEntry>,
+    ) -> Result<(String, Vec<Entry>), ConfigError> {
+        let mut file = File::open(path)?;
+        Self::check_permissions(path, &file)?;
+
+        let mut raw_conf = String::new();
+        file.read_to_string(&mut raw_conf)?;
+
+        let mut entries: Vec<Entry> = Vec::new();
+        for (line_number, line) in raw_conf.lines().map(ToString::to_string).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match Entry::try_from((line_number, &line)) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => skipped.push(SkippedEntry::new(path, line_number, &line, err)),
+            }
+        }
+
+        Ok((raw_conf, entries))
+    }
+
+    /// Returns the index of, and an error for, each entry that duplicates
+    /// an earlier entry for the same user, see [`Entry::duplicates`].
+    /// Each entry is paired with the config it was loaded from.
+    fn duplicate_entries(entries: &[(&Path, &Entry)]) -> Vec<(usize, ConfigError)> {
+        entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, entry))| {
+                let (first_path, first) = entries[..i]
+                    .iter()
+                    .find(|(_, first)| first.duplicates(entry))?;
+
+                Some((
+                    i,
+                    DuplicateEntry(
+                        entry.line_number + 1,
+                        entry.user.clone(),
+                        first_path.to_path_buf(),
+                        first.line_number + 1,
+                    ),
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the drop-in configs for the config, sorted in lexical
+    /// order, an absent drop-in directory is treated as empty.
+    fn drop_ins(path: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+        let read_dir = match std::fs::read_dir(path.with_extension("d")) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut drop_ins: Vec<PathBuf> = Vec::new();
+        for dir_entry in read_dir {
+            let drop_in = dir_entry?.path();
+            if drop_in.is_file()
+                && drop_in
+                    .extension()
+                    .is_some_and(|extension| extension == Self::DROP_IN_EXTENSION)
+            {
+                drop_ins.push(drop_in);
+            }
+        }
+
+        drop_ins.sort_unstable();
+        Ok(drop_ins)
+    }
+
+    /// Checks that only root is able to modify the config, or read
+    /// the IRKs within it, by requiring that the config and the
+    /// directory containing it are owned by root, that the config
+    /// can't be read or written by any other user and that the
+    /// directory can't be written by any other user.
+    ///
+    /// The metadata of the config is taken from the open file so that
+    /// it can't be swapped out between being checked and being read.
+    fn check_permissions(path: &Path, file: &File) -> Result<(), ConfigError> {
+        let metadata = file.metadata()?;
+        if metadata.uid() != 0 {
+            return Err(InsecurePermissions(path.into(), "it is not owned by root"));
+        }
+
+        if metadata.mode() & 0o077 != 0 {
+            return Err(InsecurePermissions(
+                path.into(),
+                "it is accessible by users other than root (expected mode 0600)",
+            ));
+        }
+
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+
+        let metadata = std::fs::metadata(parent)?;
+        if metadata.uid() != 0 {
+            return Err(InsecurePermissions(
+                parent.into(),
+                "the directory is not owned by root",
+            ));
+        }
+
+        if metadata.mode() & 0o022 != 0 {
+            return Err(InsecurePermissions(
+                parent.into(),
+                "the directory is writable by users other than root",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the entry for the user's watch with the given label,
+    /// or the user's first entry if no label is supplied.
+    #[cfg(feature = "cli")]
+    pub fn get_user(&self, user: &String, label: Option<&str>) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.matches_any(user, label))
+    }
+
+    /// Returns the entries for every watch that can unlock the user.
+    ///
+    /// Entries naming the user take precedence over entries for one
+    /// of the user's groups, which in turn take precedence over
+    /// wildcard entries, so only the entries of the most specific
+    /// kind that matches the user are returned.
+    pub fn get_user_entries(&self, user: &str) -> Vec<&Entry> {
+        let matches: Vec<(EntryKind, &Entry)> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.applies_to(user))
+            .map(|entry| (entry.kind(), entry))
+            .collect();
+
+        let Some(most_specific) = matches.iter().map(|(kind, _)| *kind).max() else {
+            return Vec::new();
+        };
+
+        matches
+            .into_iter()
+            .filter(|(kind, _)| *kind == most_specific)
+            .map(|(_, entry)| entry)
+            .collect()
     }
 
+    /// Sets the IRK of the user's watch with the given label, adding
+    /// an entry if there isn't one, and returns true if an existing
+    /// entry was updated. Without a label only the user's unlabelled
+    /// entry is updated, never one of their labelled watches.
     #[cfg(feature = "cli")]
-    pub fn update_user(&mut self, user: &String, encoded_irk: &String) -> bool {
-        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.user == *user) {
+    pub fn update_user(
+        &mut self,
+        user: &String,
+        encoded_irk: &String,
+        label: Option<&str>,
+    ) -> Result<bool, ConfigError> {
+        Entry::check_fields(user, encoded_irk, label)?;
+
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.drop_in.is_none() && entry.matches(user, label))
+        {
             entry.encoded_irk.clone_from(encoded_irk);
-            return true;
+            return Ok(true);
+        }
+
+        let mut options = EntryOptions::default();
+        if let Some(label) = label {
+            options.set(EntryOptions::LABEL, label);
         }
 
         let line_number = self.lines.len();
@@ -50,17 +296,138 @@ impl Config {
         self.entries.push(Entry {
             user: user.clone(),
             encoded_irk: encoded_irk.clone(),
+            options,
             line_number,
+            drop_in: None,
+        });
+
+        Ok(false)
+    }
+
+    /// Sets the unlock threshold for an existing watch of the user,
+    /// returning false if there isn't a matching entry to update
+    /// in the main config.
+    #[cfg(feature = "cli")]
+    pub fn set_user_unlock_threshold(
+        &mut self,
+        user: &String,
+        label: Option<&str>,
+        unlock_threshold: i16,
+    ) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.drop_in.is_none() && entry.matches(user, label))
+        {
+            Some(entry) => {
+                entry
+                    .options
+                    .set(EntryOptions::UNLOCK_THRESHOLD, unlock_threshold);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the user's entries that match the label and encoded IRK,
+    /// every entry of the user is removed if neither are supplied, and
+    /// returns how many entries were removed.
+    ///
+    /// When every entry of the user is removed, so are the user's
+    /// skipped entries, which allows a bad entry to be removed without
+    /// editing the config by hand.
+    ///
+    /// Only the lines of the removed entries are deleted from the
+    /// config, comments and blank lines are left in place. Entries
+    /// from drop-in configs are never removed.
+    #[cfg(feature = "cli")]
+    pub fn remove_user(
+        &mut self,
+        user: &String,
+        label: Option<&str>,
+        encoded_irk: Option<&str>,
+    ) -> usize {
+        let mut removed_lines: Vec<usize> = Vec::new();
+        self.entries.retain(|entry| {
+            let matches = entry.drop_in.is_none()
+                && entry.matches_any(user, label)
+                && encoded_irk.is_none_or(|encoded_irk| {
+                    entry.encoded_irk == encoded_irk
+                        || entry.irk().is_ok_and(|irk| {
+                            encoded_irk.parse().is_ok_and(|other: Irk| other == irk)
+                        })
+                });
+
+            if matches {
+                removed_lines.push(entry.line_number);
+            }
+
+            !matches
         });
 
-        false
+        let remove_all = label.is_none() && encoded_irk.is_none();
+        self.skipped.retain(|skipped| {
+            let matches = remove_all && skipped.path == self.path && skipped.user == *user;
+            if matches {
+                removed_lines.push(skipped.line_number);
+            }
+
+            !matches
+        });
+
+        removed_lines.sort_unstable();
+        for removed_line in removed_lines.iter().rev() {
+            if *removed_line < self.lines.len() {
+                self.lines.remove(*removed_line);
+            }
+        }
+
+        for entry in self
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.drop_in.is_none())
+        {
+            entry.line_number -= removed_lines
+                .iter()
+                .filter(|removed_line| **removed_line < entry.line_number)
+                .count();
+        }
+
+        for skipped in self
+            .skipped
+            .iter_mut()
+            .filter(|skipped| skipped.path == self.path)
+        {
+            skipped.line_number -= removed_lines
+                .iter()
+                .filter(|removed_line| **removed_line < skipped.line_number)
+                .count();
+        }
+
+        removed_lines.len()
     }
 
     #[cfg(feature = "cli")]
     pub fn save(&mut self) -> Result<(), ConfigError> {
+        let raw_config = self.render()?;
+        Self::write_atomically(&self.path, &raw_config, &self.loaded)?;
+
+        self.loaded = raw_config;
+        Ok(())
+    }
+
+    /// Returns the contents of the config with each entry written back
+    /// to its line, leaving every other line as it was loaded.
+    #[cfg(feature = "cli")]
+    fn render(&mut self) -> Result<String, ConfigError> {
         use crate::lib::conf::ConfigError::ConfigStateCorrupt;
 
-        for (i, entry) in self.entries.iter().enumerate() {
+        for (i, entry) in self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.drop_in.is_none())
+        {
             let Some(line) = self.lines.get_mut(entry.line_number) else {
                 return Err(ConfigStateCorrupt(i, entry.line_number));
             };
@@ -68,8 +435,101 @@ impl Config {
             *line = entry.to_string();
         }
 
-        let raw_config = self.lines.join("\n");
-        Ok(std::fs::write(Self::CONF_LOCATION, raw_config)?)
+        Ok(self.lines.join("\n"))
+    }
+
+    /// Replaces the config so that it either contains the old or the
+    /// new contents, even if the process crashes part way through.
+    ///
+    /// The new contents are written to a temporary file, with the owner
+    /// and mode of the existing config, which is synced to disk before
+    /// being renamed over the config. The previous config is kept as a
+    /// `.bak` file and an advisory lock is held throughout so that
+    /// concurrent invocations can't interleave their changes.
+    #[cfg(feature = "cli")]
+    fn write_atomically(path: &Path, contents: &str, expected: &str) -> Result<(), ConfigError> {
+        use crate::lib::conf::ConfigError::ConfigModified;
+        use std::fs::OpenOptions;
+        use std::io::{ErrorKind, Write};
+        use std::os::fd::AsRawFd;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(Self::CONF_MODE)
+            .open(Self::sibling(path, ".lock"))?;
+
+        // SAFETY: The descriptor remains open until `lock` is dropped,
+        // which also releases the lock.
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        let existing = match std::fs::metadata(path) {
+            Ok(metadata) => Some(metadata),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        if existing.is_some() && std::fs::read_to_string(path)? != expected {
+            return Err(ConfigModified);
+        }
+
+        let (mode, owner, group) = existing
+            .as_ref()
+            .map_or((Self::CONF_MODE, 0, 0), |metadata| {
+                (metadata.mode() & 0o7777, metadata.uid(), metadata.gid())
+            });
+
+        let temp_path = Self::sibling(path, &format!(".{}.tmp", std::process::id()));
+        let write_temp = || -> std::io::Result<()> {
+            let mut temp = OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .mode(Self::CONF_MODE)
+                .open(&temp_path)?;
+
+            std::os::unix::fs::fchown(&temp, Some(owner), Some(group))?;
+            temp.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            temp.write_all(contents.as_bytes())?;
+            temp.sync_all()
+        };
+
+        if let Err(err) = write_temp() {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err.into());
+        }
+
+        if existing.is_some() {
+            let backup_path = Self::sibling(path, ".bak");
+            match std::fs::remove_file(&backup_path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => std::fs::hard_link(path, &backup_path)?,
+            }
+        }
+
+        if let Err(err) = std::fs::rename(&temp_path, path) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err.into());
+        }
+
+        // Sync the directory so that the rename itself is durable
+        if let Some(parent) = path.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the path of a file alongside the config, named by
+    /// appending the suffix to the name of the config.
+    #[cfg(feature = "cli")]
+    fn sibling(path: &Path, suffix: &str) -> PathBuf {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(suffix);
+        sibling.into()
     }
 }
 
@@ -78,34 +538,454 @@ pub struct Entry {
     pub user: String,
     pub encoded_irk: String,
 
+    /// Specifies the per-user options that override the
+    /// PAM module arguments for this user.
+    pub options: EntryOptions,
+
+    /// Specifies the index of the line the entry is on, within the
+    /// config or drop-in config that it was loaded from.
+    line_number: usize,
+
+    /// Specifies the drop-in config the entry was loaded from, or
+    /// `None` if it was loaded from the main config.
+    drop_in: Option<PathBuf>,
+}
+
+impl Entry {
+    /// Specifies the prefix of an entry that applies to
+    /// the members of a group rather than a single user.
+    pub const GROUP_PREFIX: char = '@';
+
+    /// Specifies the characters that separate the fields of an entry.
+    #[cfg(feature = "cli")]
+    const FIELD_SEPARATORS: &'static [char] = &[';'];
+
+    /// Specifies the characters that separate the fields of an entry
+    /// and the key of an option from its value.
+    const OPTION_SEPARATORS: &'static [char] = &[';', '='];
+
+    /// Checks that the fields of a new entry can be written to the config
+    /// and read back, a field containing a separator or whitespace would
+    /// otherwise produce an entry that is skipped when loaded.
+    #[cfg(feature = "cli")]
+    pub fn check_fields(
+        user: &str,
+        encoded_irk: &str,
+        label: Option<&str>,
+    ) -> Result<(), ConfigError> {
+        use crate::lib::conf::ConfigError::UnwritableField;
+
+        for (field, value, separators) in [
+            ("user", Some(user), Self::FIELD_SEPARATORS),
+            ("IRK", Some(encoded_irk), Self::FIELD_SEPARATORS),
+            ("label", label, Self::OPTION_SEPARATORS),
+        ] {
+            if let Some(value) = value
+                && !is_writable(value, separators)
+            {
+                return Err(UnwritableField(field, value.to_string(), separators));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the kind of entry, which is decided by the
+    /// format of the user field.
+    pub fn kind(&self) -> EntryKind {
+        if self.user.starts_with(Self::GROUP_PREFIX) {
+            EntryKind::Group
+        } else if self.user.contains(['*', '?']) {
+            EntryKind::Wildcard
+        } else {
+            EntryKind::User
+        }
+    }
+
+    /// Returns true if the entry applies to the user, either by name,
+    /// by group membership or by matching the wildcard pattern.
+    fn applies_to(&self, user: &str) -> bool {
+        match self.kind() {
+            EntryKind::User => self.user == user,
+            EntryKind::Group => nss::user_in_group(user, &self.user[1..]),
+            EntryKind::Wildcard => wildcard_matches(self.user.as_bytes(), user.as_bytes()),
+        }
+    }
+
+    /// Returns true if the entry belongs to the user and has the given
+    /// label, an entry without a label only matches if no label is
+    /// supplied, so that a watch is never mistaken for another.
+    #[cfg(feature = "cli")]
+    fn matches(&self, user: &String, label: Option<&str>) -> bool {
+        self.user == *user && self.options.label().as_deref() == label
+    }
+
+    /// Returns true if the entry belongs to the user and has the given
+    /// label, any of the user's entries match if no label is supplied.
+    #[cfg(feature = "cli")]
+    fn matches_any(&self, user: &String, label: Option<&str>) -> bool {
+        self.user == *user
+            && label.is_none_or(|label| self.options.label().as_deref() == Some(label))
+    }
+
+    /// Returns true if both entries are for the same user and have
+    /// the same label, making it ambiguous which watch is which.
+    fn duplicates(&self, other: &Entry) -> bool {
+        self.user == other.user && self.options.label() == other.options.label()
+    }
+
+    /// Decodes the IRK of the entry, which was validated when the
+    /// entry was loaded, see [`Irk`] for the accepted encodings.
+    pub fn irk(&self) -> Result<Irk, IrkError> {
+        self.encoded_irk.parse()
+    }
+
+    /// Returns the drop-in config the entry was loaded from, entries
+    /// from drop-in configs are never modified by [`Config::save`].
+    #[cfg(feature = "cli")]
+    pub fn drop_in(&self) -> Option<&Path> {
+        self.drop_in.as_deref()
+    }
+}
+
+/// An entry that couldn't be loaded, along with why.
+#[derive(Debug)]
+pub struct SkippedEntry {
+    /// Specifies the config, or drop-in config, the entry is in.
+    pub path: PathBuf,
+
+    pub error: ConfigError,
+
+    /// Specifies the user field of the entry, as written.
+    #[cfg(feature = "cli")]
+    user: String,
+
+    /// Specifies the index of the line the entry is on.
     #[cfg(feature = "cli")]
     line_number: usize,
 }
 
+impl SkippedEntry {
+    fn new(
+        path: &Path,
+        #[cfg_attr(not(feature = "cli"), expect(unused_variables))] line_number: usize,
+        #[cfg_attr(not(feature = "cli"), expect(unused_variables))] line: &str,
+        error: ConfigError,
+    ) -> Self {
+        Self {
+            path: path.into(),
+            error,
+
+            #[cfg(feature = "cli")]
+            user: line.split(';').next().unwrap_or_default().to_string(),
+
+            #[cfg(feature = "cli")]
+            line_number,
+        }
+    }
+}
+
+impl Display for SkippedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
 impl TryFrom<(usize, &'_ String)> for Entry {
     type Error = ConfigError;
 
     fn try_from(entry_line: (usize, &String)) -> Result<Self, Self::Error> {
         let (line_number, raw_entry) = entry_line;
 
+        // Errors report line numbers starting from one, as editors do
+        let line = line_number + 1;
+
         let values: Vec<&str> = raw_entry.split(';').collect();
         if values.len() < 2 {
-            return Err(InvalidEntryCount(line_number, values.len()));
+            return Err(InvalidEntryCount(line, values.len()));
+        }
+
+        // An entry without a user would never apply to anyone
+        if values[0].trim().is_empty() {
+            return Err(MissingEntryUser(line));
+        }
+
+        if let Err(err) = values[1].parse::<Irk>() {
+            return Err(InvalidEntryIrk(line, err));
+        }
+
+        let mut options = EntryOptions::default();
+        for option in &values[2..] {
+            let Some((key, value)) = option.split_once('=') else {
+                return Err(InvalidEntryOption(line, (*option).to_string()));
+            };
+
+            if !EntryOptions::KNOWN.contains(&key) {
+                return Err(UnknownEntryOption(line, key.to_string()));
+            }
+
+            if !EntryOptions::is_valid(key, value) {
+                return Err(InvalidEntryOption(line, (*option).to_string()));
+            }
+
+            options.fields.push((key.to_string(), value.to_string()));
         }
 
         Ok(Self {
             user: values.first().expect("values length checked").to_string(),
             encoded_irk: values.get(1).expect("values length checked").to_string(),
-
-            #[cfg(feature = "cli")]
+            options,
             line_number,
+            drop_in: None,
         })
     }
 }
 
 impl Display for Entry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{};{}", self.user, self.encoded_irk)
+        write!(f, "{};{}", self.user, self.encoded_irk)?;
+
+        for (key, value) in &self.options.fields {
+            write!(f, ";{key}={value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies how an [`Entry`] is matched against a user, ordered
+/// from the least to the most specific.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryKind {
+    /// Matches any user whose name matches the pattern, where `*`
+    /// matches any run of characters and `?` any single character.
+    Wildcard,
+
+    /// Matches any user that is a member of the group named after
+    /// the `@` prefix, e.g. `@wheel`.
+    Group,
+
+    /// Matches a single user by name.
+    User,
+}
+
+/// Returns true if the name matches the wildcard pattern, where `*`
+/// matches any run of characters and `?` any single character.
+fn wildcard_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            wildcard_matches(rest, name)
+                || (!name.is_empty() && wildcard_matches(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name_rest))) => wildcard_matches(rest, name_rest),
+        (Some((expected, rest)), Some((actual, name_rest))) if expected == actual => {
+            wildcard_matches(rest, name_rest)
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if the value can be written as a field of an entry,
+/// it can't be empty or contain whitespace or any of the separators.
+fn is_writable(value: &str, separators: &[char]) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || separators.contains(&c))
+}
+
+/// Parses a `YYYY-MM-DD` date, returning the start of that day in UTC.
+fn parse_date(value: &str) -> Option<SystemTime> {
+    let mut parts = value.splitn(3, '-');
+    let year: u64 = parts.next().filter(|year| year.len() == 4)?.parse().ok()?;
+    let month: u64 = parts
+        .next()
+        .filter(|month| month.len() == 2)?
+        .parse()
+        .ok()?;
+    let day: u64 = parts.next().filter(|day| day.len() == 2)?.parse().ok()?;
+
+    let leap_year =
+        year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let month_days = match month {
+        2 if leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        _ => return None,
+    };
+
+    if year < 1970 || day == 0 || day > month_days {
+        return None;
+    }
+
+    // Counts the days since the Unix epoch using the civil calendar
+    // algorithm, with years starting in March so that the leap day
+    // is the last day of the year.
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    UNIX_EPOCH.checked_add(Duration::from_secs(days * 86_400))
+}
+
+/// The optional `key=value` fields of an [`Entry`], the fields are
+/// kept in the order they were written so that they are preserved
+/// when the config is saved.
+#[derive(Debug, Default)]
+pub struct EntryOptions {
+    fields: Vec<(String, String)>,
+}
+
+impl EntryOptions {
+    pub const UNLOCK_THRESHOLD: &'static str = "unlock_threshold";
+    pub const RETRIES: &'static str = "retries";
+    pub const RETRY_TIMEOUT: &'static str = "retry_timeout";
+    pub const ADAPTER: &'static str = "adapter";
+    pub const REQUIRE_AUTO_UNLOCK: &'static str = "require_auto_unlock";
+    pub const ENABLED: &'static str = "enabled";
+    pub const EXPIRES: &'static str = "expires";
+    pub const LABEL: &'static str = "label";
+
+    /// Specifies every option that may be set on an entry.
+    pub const KNOWN: [&'static str; 8] = [
+        Self::UNLOCK_THRESHOLD,
+        Self::RETRIES,
+        Self::RETRY_TIMEOUT,
+        Self::ADAPTER,
+        Self::REQUIRE_AUTO_UNLOCK,
+        Self::ENABLED,
+        Self::EXPIRES,
+        Self::LABEL,
+    ];
+
+    /// Checks that the value of a known option can be parsed.
+    fn is_valid(key: &str, value: &str) -> bool {
+        match key {
+            Self::UNLOCK_THRESHOLD => value.parse::<i16>().is_ok(),
+            Self::RETRIES => value.parse::<u8>().is_ok(),
+            Self::RETRY_TIMEOUT => value.parse::<u64>().is_ok(),
+            Self::ADAPTER => !value.is_empty(),
+            Self::LABEL => is_writable(value, Entry::OPTION_SEPARATORS),
+            Self::REQUIRE_AUTO_UNLOCK | Self::ENABLED => value.parse::<bool>().is_ok(),
+            Self::EXPIRES => parse_date(value).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Returns a warning if the value of a known option, which can
+    /// be parsed, is outside of the range that makes sense for it.
+    #[cfg(feature = "cli")]
+    fn range_warning(key: &str, value: &str) -> Option<&'static str> {
+        match (key, value.parse::<i64>()) {
+            (Self::UNLOCK_THRESHOLD, Ok(threshold)) if !(-127..=0).contains(&threshold) => {
+                Some("RSSI is reported between -127 and 0 dBm")
+            }
+            (Self::RETRIES, Ok(0)) => Some("the Apple Watch will never be searched for"),
+            (Self::RETRY_TIMEOUT, Ok(0)) => Some("the Apple Watch will never be found"),
+            (Self::RETRY_TIMEOUT, Ok(timeout)) if timeout > 10_000 => {
+                Some("authentication may stall for a long time if the watch isn't nearby")
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the option, parsed as `T`, if it is set.
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == key)
+            .and_then(|(_, value)| value.parse().ok())
+    }
+
+    /// Specifies a per-user unlock threshold that overrides
+    /// the `unlock_threshold` PAM module argument.
+    pub fn unlock_threshold(&self) -> Option<i16> {
+        self.get(Self::UNLOCK_THRESHOLD)
+    }
+
+    /// Specifies how many times to search for the user's
+    /// Apple Watch before giving up.
+    pub fn retries(&self) -> Option<u8> {
+        self.get(Self::RETRIES)
+    }
+
+    /// Specifies how long, in milliseconds, each search for
+    /// the user's Apple Watch may take.
+    pub fn retry_timeout(&self) -> Option<u64> {
+        self.get(Self::RETRY_TIMEOUT)
+    }
+
+    /// Specifies the name of the Bluetooth adapter (e.g. `hci1`)
+    /// to search for the user's Apple Watch with.
+    pub fn adapter(&self) -> Option<String> {
+        self.get(Self::ADAPTER)
+    }
+
+    /// Specifies if the user's Apple Watch must have auto-unlock
+    /// enabled to unlock this device.
+    pub fn require_auto_unlock(&self) -> Option<bool> {
+        self.get(Self::REQUIRE_AUTO_UNLOCK)
+    }
+
+    /// Specifies if unlocking with an Apple Watch is enabled
+    /// for the user.
+    pub fn enabled(&self) -> Option<bool> {
+        self.get(Self::ENABLED)
+    }
+
+    /// Specifies when unlocking with the Apple Watch stops being
+    /// allowed, written as a `YYYY-MM-DD` date in UTC.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == Self::EXPIRES)
+            .and_then(|(_, value)| parse_date(value))
+    }
+
+    /// Returns true if the entry has an expiry date that has passed.
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires().is_some_and(|expires| expires <= now)
+    }
+
+    /// Returns true if the Apple Watch of the entry may be used
+    /// to unlock this device, it must be enabled and not expired.
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.enabled() != Some(false) && !self.is_expired(now)
+    }
+
+    /// Specifies a name for the watch, distinguishing it from
+    /// the other watches registered to the same user.
+    pub fn label(&self) -> Option<String> {
+        self.get(Self::LABEL)
+    }
+
+    /// Returns the options as `key=value` pairs, in the
+    /// order they are written in the config.
+    #[cfg(feature = "cli")]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Sets the value of the option, replacing the existing value
+    /// in place or appending the option if it wasn't already set.
+    #[cfg(feature = "cli")]
+    pub fn set(&mut self, key: &str, value: impl Display) {
+        let value = value.to_string();
+        match self.fields.iter_mut().find(|(field, _)| field == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.fields.push((key.to_string(), value)),
+        }
     }
 }
 
@@ -117,7 +997,326 @@ pub enum ConfigError {
     #[error("Config entry (line {0}) has the wrong number ({1}) of entries")]
     InvalidEntryCount(usize, usize),
 
+    #[error("Config entry (line {0}) has no user")]
+    MissingEntryUser(usize),
+
+    #[error("Config entry (line {0}) has an invalid option: {1}")]
+    InvalidEntryOption(usize, String),
+
+    #[error("Config entry (line {0}) has an unknown option: {1}")]
+    UnknownEntryOption(usize, String),
+
+    #[error("Config entry (line {0}) has an invalid IRK: {1}")]
+    InvalidEntryIrk(usize, IrkError),
+
+    #[error(
+        "Config entry (line {0}) duplicates the entry for '{1}' in {path} on line {3}, each of a user's watches needs a distinct label",
+        path = .2.display()
+    )]
+    DuplicateEntry(usize, String, PathBuf, usize),
+
+    #[error("Refusing to use {path}, {1}", path = .0.display())]
+    InsecurePermissions(PathBuf, &'static str),
+
+    #[error("Drop-in config {path} is invalid: {1}", path = .0.display())]
+    InvalidDropIn(PathBuf, Box<ConfigError>),
+
     #[cfg(feature = "cli")]
     #[error("Config entry {0} was expected to be on line {1} but it didn't exist")]
     ConfigStateCorrupt(usize, usize),
+
+    #[cfg(feature = "cli")]
+    #[error("Config was modified by another process since it was loaded, please try again")]
+    ConfigModified,
+
+    #[cfg(feature = "cli")]
+    #[error("Invalid {0} {1:?}, it must not be empty or contain whitespace or any of {2:?}")]
+    UnwritableField(&'static str, String, &'static [char]),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+    const OTHER_IRK: &str = "UMMMehk4THhKK0QTP2BFXg==";
+
+    /// Parses a config from its contents, as [`Config::load`] does but
+    /// without reading a file or checking its permissions.
+    fn config(raw_conf: &str) -> Config {
+        let entries = raw_conf
+            .lines()
+            .map(ToString::to_string)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_number, line)| Entry::try_from((line_number, &line)).expect("valid entry"))
+            .collect();
+
+        Config {
+            entries,
+            skipped: Vec::new(),
+
+            #[cfg(feature = "cli")]
+            path: PathBuf::new(),
+
+            #[cfg(feature = "cli")]
+            lines: raw_conf.lines().map(ToString::to_string).collect(),
+
+            #[cfg(feature = "cli")]
+            loaded: raw_conf.to_string(),
+        }
+    }
+
+    #[test]
+    fn user_entries_include_every_watch() {
+        let config = config(&format!(
+            "alice;{IRK};label=sport\nbob;{IRK}\nalice;{OTHER_IRK};label=dress"
+        ));
+
+        let labels: Vec<Option<String>> = config
+            .get_user_entries("alice")
+            .iter()
+            .map(|entry| entry.options.label())
+            .collect();
+        assert_eq!(
+            labels,
+            [Some("sport".to_string()), Some("dress".to_string())]
+        );
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn update_without_label_keeps_labelled_watches() {
+        let mut config = config(&format!("alice;{IRK};label=sport"));
+
+        assert!(
+            !config
+                .update_user(&"alice".to_string(), &OTHER_IRK.to_string(), None)
+                .expect("valid fields")
+        );
+        assert_eq!(
+            config.render().expect("rendered"),
+            format!("alice;{IRK};label=sport\nalice;{OTHER_IRK}")
+        );
+
+        // The unlabelled entry is now the one that is updated
+        assert!(
+            config
+                .update_user(&"alice".to_string(), &IRK.to_string(), None)
+                .expect("valid fields")
+        );
+        assert_eq!(config.entries.len(), 2);
+        assert_eq!(config.entries[1].encoded_irk, IRK);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn update_with_label_updates_only_that_watch() {
+        let mut config = config(&format!("alice;{IRK}\nalice;{IRK};label=sport"));
+
+        assert!(
+            config
+                .update_user(&"alice".to_string(), &OTHER_IRK.to_string(), Some("sport"))
+                .expect("valid fields")
+        );
+        assert_eq!(config.entries[0].encoded_irk, IRK);
+        assert_eq!(config.entries[1].encoded_irk, OTHER_IRK);
+
+        assert!(
+            !config
+                .update_user(&"alice".to_string(), &OTHER_IRK.to_string(), Some("dress"))
+                .expect("valid fields")
+        );
+        assert_eq!(config.entries.len(), 3);
+    }
+
+    #[test]
+    fn wildcard_matches_any_run_of_characters() {
+        for (pattern, name) in [
+            ("*", ""),
+            ("*", "alice"),
+            ("lab-*", "lab-"),
+            ("lab-*", "lab-alice"),
+            ("*-lab", "alice-lab"),
+            ("*-lab-*", "alice-lab-1"),
+            ("a*e", "alice"),
+            ("a**e", "ae"),
+            ("lab-?", "lab-1"),
+            ("?lice", "alice"),
+        ] {
+            assert!(
+                wildcard_matches(pattern.as_bytes(), name.as_bytes()),
+                "{pattern:?} {name:?}"
+            );
+        }
+
+        for (pattern, name) in [
+            ("lab-*", "lab"),
+            ("lab-*", "alice-lab-1"),
+            ("*-lab", "alice-lab-1"),
+            ("*-lab", "lab"),
+            ("a*e", "alicia"),
+            ("lab-?", "lab-"),
+            ("lab-?", "lab-12"),
+            ("alice", "alicia"),
+        ] {
+            assert!(
+                !wildcard_matches(pattern.as_bytes(), name.as_bytes()),
+                "{pattern:?} {name:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn wildcard_entries_apply_to_matching_users() {
+        let config = config(&format!(
+            "*-admin;{IRK}
+lab-*;{OTHER_IRK}"
+        ));
+
+        assert_eq!(config.entries[0].kind(), EntryKind::Wildcard);
+        assert!(config.entries[0].applies_to("alice-admin"));
+        assert!(!config.entries[0].applies_to("admin"));
+        assert!(config.entries[1].applies_to("lab-alice"));
+        assert!(!config.entries[1].applies_to("alice"));
+    }
+
+    #[test]
+    fn entries_without_a_user_are_rejected() {
+        for line in [format!(";{IRK}"), format!(" ;{IRK};label=sport")] {
+            assert!(
+                matches!(Entry::try_from((0, &line)), Err(MissingEntryUser(1))),
+                "{line:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_date_counts_days_since_the_epoch() {
+        let date = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(parse_date("1970-01-01"), date(0));
+        assert_eq!(parse_date("2000-03-01"), date(951_868_800));
+        assert_eq!(parse_date("2024-02-29"), date(1_709_164_800));
+        assert!(parse_date("2000-02-29").is_some());
+    }
+
+    #[test]
+    fn parse_date_rejects_invalid_dates() {
+        for value in [
+            "",
+            "2024",
+            "2024-01",
+            "2024-1-01",
+            "24-01-01",
+            "2024-01-1",
+            "2024-01-01T00",
+            "2024/01/01",
+            "2024-00-10",
+            "2024-13-01",
+            "2024-01-00",
+            "2024-01-32",
+            "2024-04-31",
+            "2023-02-29",
+            "2100-02-29",
+            "1969-12-31",
+            "+024-01-01",
+        ] {
+            assert_eq!(parse_date(value), None, "{value:?}");
+        }
+
+        let line = format!("alice;{IRK};expires=2023-02-29");
+        assert!(matches!(
+            Entry::try_from((0, &line)),
+            Err(InvalidEntryOption(1, _))
+        ));
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn options_keep_their_order_when_saved() {
+        let mut config = config(&format!(
+            "alice;{IRK};label=sport;unlock_threshold=-70;expires=2030-01-01;enabled=true"
+        ));
+
+        let options: Vec<(&str, &str)> = config.entries[0].options.iter().collect();
+        assert_eq!(
+            options,
+            [
+                ("label", "sport"),
+                ("unlock_threshold", "-70"),
+                ("expires", "2030-01-01"),
+                ("enabled", "true"),
+            ]
+        );
+
+        // An existing option is replaced in place, a new one appended
+        assert!(config.set_user_unlock_threshold(&"alice".to_string(), Some("sport"), -60));
+        config.entries[0].options.set(EntryOptions::RETRIES, 3);
+        assert_eq!(
+            config.render().expect("rendered"),
+            format!(
+                "alice;{IRK};label=sport;unlock_threshold=-60;expires=2030-01-01;enabled=true;retries=3"
+            )
+        );
+    }
+
+    #[test]
+    fn label_option_rejects_separators() {
+        assert!(EntryOptions::is_valid(EntryOptions::LABEL, "sport"));
+        for label in ["", "a b", "a=b", "a\tb", "a\nb"] {
+            assert!(
+                !EntryOptions::is_valid(EntryOptions::LABEL, label),
+                "{label:?}"
+            );
+        }
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn check_fields_rejects_unwritable_values() {
+        assert!(Entry::check_fields("alice", IRK, Some("sport")).is_ok());
+        assert!(Entry::check_fields("@wheel", IRK, None).is_ok());
+        assert!(Entry::check_fields("lab-*", IRK, None).is_ok());
+
+        for (user, irk, label) in [
+            ("", IRK, None),
+            ("alice bob", IRK, None),
+            ("alice;bob", IRK, None),
+            ("alice", "AAAA;label=x", None),
+            ("alice", "AAAA\nbob;AAAA", None),
+            ("alice", IRK, Some("")),
+            ("alice", IRK, Some("my watch")),
+            ("alice", IRK, Some("a;b")),
+            ("alice", IRK, Some("a=b")),
+            ("alice", IRK, Some("a\nb")),
+        ] {
+            assert!(
+                Entry::check_fields(user, irk, label).is_err(),
+                "{user:?} {irk:?} {label:?}"
+            );
+        }
+
+        let mut config = config("");
+        assert!(
+            config
+                .update_user(&"alice".to_string(), &IRK.to_string(), Some("a;b"))
+                .is_err()
+        );
+        assert!(config.entries.is_empty());
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn remove_without_label_removes_every_watch() {
+        let mut config = config(&format!(
+            "# watches\nalice;{IRK}\nalice;{OTHER_IRK};label=sport\nbob;{IRK}"
+        ));
+
+        assert_eq!(config.remove_user(&"alice".to_string(), None, None), 2);
+        assert_eq!(
+            config.render().expect("rendered"),
+            format!("# watches\nbob;{IRK}")
+        );
+    }
 }
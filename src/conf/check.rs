@@ -0,0 +1,213 @@
+use crate::lib::conf::{Config, Entry, EntryKind, EntryOptions};
+use crate::lib::nss;
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Identifies how serious a [`ConfigIssue`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The config will be loaded, but it may not behave as intended.
+    Warning,
+
+    /// The PAM module will refuse to load the config, or skip the entry.
+    Error,
+}
+
+/// A problem found while checking a config.
+#[derive(Debug)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+
+    /// Specifies the config, or drop-in config, the problem was found in.
+    pub path: PathBuf,
+
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(severity: Severity, path: &Path, message: impl Display) -> Self {
+        Self {
+            severity,
+            path: path.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl Config {
+    /// Checks the config at the supplied path, and its drop-in configs,
+    /// returning every problem found, including those [`Config::load`]
+    /// doesn't check for, such as users that don't exist.
+    ///
+    /// The config is loaded exactly as the PAM module loads it, so a
+    /// config it refuses is a single error and each entry it skips is
+    /// reported with why.
+    pub fn check(path: &Path) -> Vec<ConfigIssue> {
+        let config = match Self::load(path) {
+            Ok(config) => config,
+            Err(err) => return vec![ConfigIssue::new(Severity::Error, path, err)],
+        };
+
+        let mut issues: Vec<ConfigIssue> = config
+            .skipped
+            .iter()
+            .map(|skipped| ConfigIssue::new(Severity::Error, &skipped.path, &skipped.error))
+            .collect();
+
+        for entry in &config.entries {
+            Self::check_entry(entry.drop_in.as_deref().unwrap_or(path), entry, &mut issues);
+        }
+
+        Self::check_shared_irks(path, &config.entries, &mut issues);
+        issues
+    }
+
+    /// Checks that the user, or group, of the entry exists and that
+    /// its options are within range.
+    fn check_entry(path: &Path, entry: &Entry, issues: &mut Vec<ConfigIssue>) {
+        let line = entry.line_number + 1;
+
+        match entry.kind() {
+            EntryKind::User if !nss::user_exists(&entry.user) => issues.push(ConfigIssue::new(
+                Severity::Warning,
+                path,
+                format!(
+                    "Config entry (line {line}) is for user '{}' who doesn't exist",
+                    entry.user
+                ),
+            )),
+            EntryKind::Group if !nss::group_exists(&entry.user[1..]) => {
+                issues.push(ConfigIssue::new(
+                    Severity::Warning,
+                    path,
+                    format!(
+                        "Config entry (line {line}) is for group '{}' which doesn't exist",
+                        &entry.user[1..]
+                    ),
+                ));
+            }
+            _ => {}
+        }
+
+        if entry.options.is_expired(SystemTime::now()) {
+            issues.push(ConfigIssue::new(
+                Severity::Warning,
+                path,
+                format!("Config entry (line {line}) has expired, the Apple Watch can no longer unlock this device"),
+            ));
+        }
+
+        for (key, value) in entry.options.iter() {
+            if let Some(warning) = EntryOptions::range_warning(key, value) {
+                issues.push(ConfigIssue::new(
+                    Severity::Warning,
+                    path,
+                    format!("Config entry (line {line}) has an out of range option {key}={value}, {warning}"),
+                ));
+            }
+        }
+    }
+
+    /// Checks that each IRK is only registered to a single user, a
+    /// watch shared between users unlocks all of them.
+    ///
+    /// Group and wildcard entries are meant to share a watch between
+    /// users, so only the entries that name a user are compared.
+    fn check_shared_irks(path: &Path, entries: &[Entry], issues: &mut Vec<ConfigIssue>) {
+        let entries: Vec<&Entry> = entries
+            .iter()
+            .filter(|entry| entry.kind() == EntryKind::User)
+            .collect();
+
+        for (i, entry) in entries.iter().enumerate() {
+            let Some(first) = entries[..i]
+                .iter()
+                .find(|first| first.user != entry.user && first.irk().ok() == entry.irk().ok())
+            else {
+                continue;
+            };
+
+            issues.push(ConfigIssue::new(
+                Severity::Warning,
+                entry.drop_in.as_deref().unwrap_or(path),
+                format!(
+                    "Config entry (line {}) shares its IRK with the entry for '{}' ({}, line {})",
+                    entry.line_number + 1,
+                    first.user,
+                    first.drop_in.as_deref().unwrap_or(path).display(),
+                    first.line_number + 1,
+                ),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+    const OTHER_IRK: &str = "Zt0bC3Hq5dyKcA2N8zFfMw==";
+
+    /// Returns the issues found with the IRKs of the entries.
+    fn shared_irk_issues<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<ConfigIssue> {
+        let entries: Vec<Entry> = lines
+            .into_iter()
+            .enumerate()
+            .map(|(line_number, line)| {
+                Entry::try_from((line_number, &line.to_string())).expect("valid entry")
+            })
+            .collect();
+
+        let mut issues = Vec::new();
+        Config::check_shared_irks(Path::new("apple_watch.conf"), &entries, &mut issues);
+        issues
+    }
+
+    #[test]
+    fn irk_shared_between_users_is_reported() {
+        let issues = shared_irk_issues([
+            format!("alice;{IRK}").as_str(),
+            format!("bob;{OTHER_IRK}").as_str(),
+            format!("carol;{IRK}").as_str(),
+        ]);
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(issues[0].message.contains("line 3"), "{}", issues[0]);
+        assert!(issues[0].message.contains("'alice'"), "{}", issues[0]);
+    }
+
+    #[test]
+    fn irk_shared_by_group_and_wildcard_entries_is_allowed() {
+        let issues = shared_irk_issues([
+            format!("alice;{IRK}").as_str(),
+            format!("alice;{IRK};label=spare").as_str(),
+            format!("@wheel;{IRK}").as_str(),
+            format!("lab-*;{IRK}").as_str(),
+        ]);
+
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn users_with_their_own_irks_are_not_reported() {
+        let issues = shared_irk_issues([
+            format!("admin;{IRK}").as_str(),
+            format!("staff;{OTHER_IRK};unlock_threshold=-70;adapter=hci1").as_str(),
+            "staff;m305CqYQEDQFrchXozQC7A==;label=sport",
+            "guest;Ba6qYq8S1M2hNnSk6jJ4Tw==;expires=2026-12-31",
+            format!("@wheel;{IRK};label=shared").as_str(),
+        ]);
+
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+}
@@ -0,0 +1,446 @@
+use crate::lib::continuity::ContinuityError::{MessageTooShort, MessageTruncated};
+
+use std::fmt::Display;
+use thiserror::Error;
+
+/// An iterator over the type-length-value encoded Apple Continuity
+/// messages packed into the manufacturer data of an advertisement.
+///
+/// Iteration stops after the first malformed message is returned.
+pub struct ContinuityMessages<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ContinuityMessages<'a> {
+    /// Creates an iterator over the messages in the manufacturer
+    /// data advertised under Apple's company identifier.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for ContinuityMessages<'a> {
+    type Item = Result<ContinuityMessage<'a>, ContinuityError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [message_type, length, remaining @ ..] = self.data else {
+            if self.data.is_empty() {
+                return None;
+            }
+
+            self.data = &[];
+            return Some(Err(MessageTruncated("header", 2, 1)));
+        };
+
+        let message_type = ContinuityMessageType::from(*message_type);
+        let length = usize::from(*length);
+
+        let Some((data, remaining)) = remaining.split_at_checked(length) else {
+            let available = remaining.len();
+            self.data = &[];
+            return Some(Err(MessageTruncated(
+                message_type.name(),
+                length,
+                available,
+            )));
+        };
+
+        self.data = remaining;
+        Some(Ok(ContinuityMessage { message_type, data }))
+    }
+}
+
+/// A single Apple Continuity message, the data of the message
+/// excludes the type and length header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContinuityMessage<'a> {
+    pub message_type: ContinuityMessageType,
+    pub data: &'a [u8],
+}
+
+/// Identifies the type of an Apple Continuity message.
+///
+/// Reference: <https://github.com/furiousMAC/continuity/tree/master/messages>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContinuityMessageType {
+    IBeacon,
+    AirPrint,
+    AirDrop,
+    HomeKit,
+    ProximityPairing,
+    HeySiri,
+    AirPlayTarget,
+    AirPlaySource,
+    MagicSwitch,
+    Handoff,
+    TetheringTarget,
+    TetheringSource,
+    NearbyAction,
+    NearbyInfo,
+    FindMy,
+    Unknown(u8),
+}
+
+impl ContinuityMessageType {
+    /// Returns a human-readable name for the message type.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::IBeacon => "iBeacon",
+            Self::AirPrint => "AirPrint",
+            Self::AirDrop => "AirDrop",
+            Self::HomeKit => "HomeKit",
+            Self::ProximityPairing => "Proximity Pairing",
+            Self::HeySiri => "Hey Siri",
+            Self::AirPlayTarget => "AirPlay Target",
+            Self::AirPlaySource => "AirPlay Source",
+            Self::MagicSwitch => "Magic Switch",
+            Self::Handoff => "Handoff",
+            Self::TetheringTarget => "Tethering Target Presence",
+            Self::TetheringSource => "Tethering Source Presence",
+            Self::NearbyAction => "Nearby Action",
+            Self::NearbyInfo => "Nearby Info",
+            Self::FindMy => "Find My",
+            Self::Unknown(_) => "Unknown",
+        }
+    }
+}
+
+impl From<u8> for ContinuityMessageType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x02 => Self::IBeacon,
+            0x03 => Self::AirPrint,
+            0x05 => Self::AirDrop,
+            0x06 => Self::HomeKit,
+            0x07 => Self::ProximityPairing,
+            0x08 => Self::HeySiri,
+            0x09 => Self::AirPlayTarget,
+            0x0A => Self::AirPlaySource,
+            0x0B => Self::MagicSwitch,
+            0x0C => Self::Handoff,
+            0x0D => Self::TetheringTarget,
+            0x0E => Self::TetheringSource,
+            0x0F => Self::NearbyAction,
+            0x10 => Self::NearbyInfo,
+            0x12 => Self::FindMy,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl Display for ContinuityMessageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown(message_type) => write!(f, "Unknown ({message_type:#04x})"),
+            known => write!(f, "{}", known.name()),
+        }
+    }
+}
+
+/// A decoded Apple Continuity Nearby Information message, which Apple
+/// devices advertise to describe their current state to other devices
+/// owned by the same user.
+///
+/// Reference: <https://github.com/furiousMAC/continuity/blob/master/messages/nearby_info.md>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearbyInfo {
+    /// Specifies the status flags from the upper nibble
+    /// of the first byte of the message.
+    pub status_flags: u8,
+
+    /// Specifies the action code from the lower nibble
+    /// of the first byte of the message.
+    pub action_code: NearbyActionCode,
+
+    /// Specifies the raw data flags from the second
+    /// byte of the message.
+    pub data_flags: u8,
+
+    /// Specifies the authentication tag that follows the
+    /// data flags, it is derived from a key shared between
+    /// the devices of the user.
+    pub authentication_tag: [u8; 3],
+}
+
+impl NearbyInfo {
+    /// Specifies the minimum length, in bytes, of a Nearby Information
+    /// message. Newer OS versions may append additional bytes after
+    /// the authentication tag which are ignored.
+    pub const MIN_LENGTH: usize = 5;
+
+    /// Specifies the bit-flag for the Wi-Fi state of the device
+    /// within the data flags segment of a Nearby Information
+    /// message.
+    #[cfg(feature = "cli")]
+    const DATA_FLAG_WIFI_ON: u8 = 0x04;
+
+    /// Specifies the bit-flag for the Apple Watch Locked status
+    /// within the data flags segment of a Nearby Information
+    /// message.
+    const DATA_FLAG_WATCH_LOCKED: u8 = 0x20;
+
+    /// Specifies the bit-flag for the Apple Watch Auto Unlock,
+    /// for other devices (e.g. Macbook or iPhone), within the
+    /// data flags segment of a Nearby Information message.
+    const DATA_FLAG_AUTO_UNLOCK_ENABLED: u8 = 0x80;
+
+    /// Specifies the bits of the lower nibble of the data flags
+    /// that have been observed to change with the OS version of
+    /// the advertising device.
+    #[cfg(feature = "cli")]
+    const DATA_FLAG_OS_VERSION_MASK: u8 = 0x0F & !Self::DATA_FLAG_WIFI_ON;
+
+    /// Returns true if the Apple Watch is currently locked.
+    pub fn watch_locked(&self) -> bool {
+        (self.data_flags & Self::DATA_FLAG_WATCH_LOCKED) != 0
+    }
+
+    /// Returns true if the Apple Watch is configured to allow
+    /// for the unlocking of remote devices (e.g. Macbook, iPhone).
+    pub fn auto_unlock_enabled(&self) -> bool {
+        (self.data_flags & Self::DATA_FLAG_AUTO_UNLOCK_ENABLED) != 0
+    }
+
+    /// Returns true if the advertising device has Wi-Fi turned on.
+    #[cfg(feature = "cli")]
+    pub fn wifi_on(&self) -> bool {
+        (self.data_flags & Self::DATA_FLAG_WIFI_ON) != 0
+    }
+
+    /// Returns the OS version nibble of the data flags, the mapping
+    /// of this value to a specific OS release is not documented.
+    #[cfg(feature = "cli")]
+    pub fn os_version(&self) -> u8 {
+        self.data_flags & Self::DATA_FLAG_OS_VERSION_MASK
+    }
+}
+
+impl NearbyInfo {
+    /// Finds the Nearby Information message among the messages in the
+    /// manufacturer data, returning it along with the types of the other
+    /// messages, or `None` if there is no Nearby Information message.
+    ///
+    /// Apple devices commonly pack several messages into a single
+    /// advertisement, so every message is searched rather than assuming
+    /// it is first. Only the first Nearby Information message is decoded.
+    pub fn find(
+        data: &[u8],
+    ) -> Result<Option<(Self, Vec<ContinuityMessageType>)>, ContinuityError> {
+        let mut nearby_info = None;
+        let mut other_messages = Vec::new();
+        for message in ContinuityMessages::new(data) {
+            let message = match message {
+                Ok(message) => message,
+
+                // A malformed trailing message is tolerated as long as
+                // an intact Nearby Info message has already been found
+                Err(_) if nearby_info.is_some() => break,
+                Err(err) => return Err(err),
+            };
+
+            match message.message_type {
+                ContinuityMessageType::NearbyInfo if nearby_info.is_none() => {
+                    nearby_info = Some(Self::try_from(message.data)?);
+                }
+                message_type => other_messages.push(message_type),
+            }
+        }
+
+        Ok(nearby_info.map(|nearby_info| (nearby_info, other_messages)))
+    }
+}
+
+impl TryFrom<&'_ [u8]> for NearbyInfo {
+    type Error = ContinuityError;
+
+    /// Decodes a Nearby Information message from the message data
+    /// that follows the type and length header.
+    fn try_from(message: &[u8]) -> Result<Self, Self::Error> {
+        let [status_and_action, data_flags, tag_0, tag_1, tag_2, ..] = *message else {
+            return Err(MessageTooShort(
+                "Nearby Info",
+                Self::MIN_LENGTH,
+                message.len(),
+            ));
+        };
+
+        Ok(Self {
+            status_flags: status_and_action >> 4,
+            action_code: NearbyActionCode::from(status_and_action & 0x0F),
+            data_flags,
+            authentication_tag: [tag_0, tag_1, tag_2],
+        })
+    }
+}
+
+/// Describes the activity of the user on the advertising device.
+///
+/// Reference: <https://github.com/furiousMAC/continuity/blob/master/messages/nearby_info.md>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NearbyActionCode {
+    ActivityLevelUnknown,
+    ActivityReportingDisabled,
+    Idle,
+    AudioPlayingScreenLocked,
+    ScreenOn,
+    ScreenOnVideoPlaying,
+    WatchOnWristUnlocked,
+    RecentUserInteraction,
+    Driving,
+    Call,
+    Unknown(u8),
+}
+
+impl From<u8> for NearbyActionCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => Self::ActivityLevelUnknown,
+            0x01 => Self::ActivityReportingDisabled,
+            0x03 => Self::Idle,
+            0x05 => Self::AudioPlayingScreenLocked,
+            0x07 => Self::ScreenOn,
+            0x09 => Self::ScreenOnVideoPlaying,
+            0x0A => Self::WatchOnWristUnlocked,
+            0x0B => Self::RecentUserInteraction,
+            0x0D => Self::Driving,
+            0x0E => Self::Call,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl Display for NearbyActionCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ActivityLevelUnknown => write!(f, "activity level unknown"),
+            Self::ActivityReportingDisabled => write!(f, "activity reporting disabled"),
+            Self::Idle => write!(f, "idle"),
+            Self::AudioPlayingScreenLocked => write!(f, "audio playing, screen locked"),
+            Self::ScreenOn => write!(f, "screen on"),
+            Self::ScreenOnVideoPlaying => write!(f, "screen on, video playing"),
+            Self::WatchOnWristUnlocked => write!(f, "on wrist and unlocked"),
+            Self::RecentUserInteraction => write!(f, "recent user interaction"),
+            Self::Driving => write!(f, "driving"),
+            Self::Call => write!(f, "phone or FaceTime call"),
+            Self::Unknown(code) => write!(f, "unknown ({code:#x})"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ContinuityError {
+    #[error("{0} message must be at least {1} bytes long, got {2}")]
+    MessageTooShort(&'static str, usize, usize),
+
+    #[error("{0} message declares {1} bytes but only {2} are available")]
+    MessageTruncated(&'static str, usize, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ContinuityError, ContinuityMessageType, ContinuityMessages, NearbyActionCode, NearbyInfo,
+    };
+
+    /// Specifies a Nearby Info message captured from an unlocked
+    /// Apple Watch with auto-unlock enabled.
+    const NEARBY_INFO: [u8; 7] = [0x10, 0x05, 0x01, 0x98, 0x1c, 0x6f, 0x3a];
+
+    /// Specifies a Handoff message captured from the same watch.
+    const HANDOFF: [u8; 16] = [
+        0x0c, 0x0e, 0x00, 0xc7, 0x3e, 0x5a, 0x4b, 0x8f, 0x1e, 0x2c, 0x41, 0x02, 0x95, 0x07, 0x3a,
+        0x0b,
+    ];
+
+    #[test]
+    fn nearby_info_is_decoded() {
+        let (nearby_info, other_messages) = NearbyInfo::find(&NEARBY_INFO).unwrap().unwrap();
+
+        assert_eq!(nearby_info.status_flags, 0);
+        assert_eq!(
+            nearby_info.action_code,
+            NearbyActionCode::ActivityReportingDisabled
+        );
+        assert_eq!(nearby_info.authentication_tag, [0x1c, 0x6f, 0x3a]);
+        assert!(nearby_info.auto_unlock_enabled());
+        assert!(!nearby_info.watch_locked());
+        assert!(other_messages.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn data_flags_are_decoded() {
+        let (nearby_info, _) = NearbyInfo::find(&NEARBY_INFO).unwrap().unwrap();
+
+        assert!(!nearby_info.wifi_on());
+        assert_eq!(nearby_info.os_version(), 0x08);
+    }
+
+    #[test]
+    fn nearby_info_is_found_after_another_message() {
+        let data = [HANDOFF.as_slice(), &NEARBY_INFO].concat();
+        let (nearby_info, other_messages) = NearbyInfo::find(&data).unwrap().unwrap();
+
+        assert_eq!(nearby_info.data_flags, 0x98);
+        assert_eq!(other_messages, [ContinuityMessageType::Handoff]);
+    }
+
+    #[test]
+    fn only_the_first_nearby_info_is_used() {
+        let mut locked = NEARBY_INFO;
+        locked[3] |= 0x20;
+
+        let data = [NEARBY_INFO, locked].concat();
+        let (nearby_info, other_messages) = NearbyInfo::find(&data).unwrap().unwrap();
+
+        assert!(!nearby_info.watch_locked());
+        assert_eq!(other_messages, [ContinuityMessageType::NearbyInfo]);
+    }
+
+    #[test]
+    fn unknown_messages_are_skipped() {
+        let data = [[0x42, 0x02, 0xaa, 0xbb].as_slice(), &NEARBY_INFO].concat();
+        let (_, other_messages) = NearbyInfo::find(&data).unwrap().unwrap();
+
+        assert_eq!(other_messages, [ContinuityMessageType::Unknown(0x42)]);
+        assert_eq!(other_messages[0].to_string(), "Unknown (0x42)");
+    }
+
+    #[test]
+    fn missing_nearby_info_is_not_found() {
+        assert!(NearbyInfo::find(&HANDOFF).unwrap().is_none());
+        assert!(NearbyInfo::find(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_messages_are_rejected() {
+        let err = NearbyInfo::find(&NEARBY_INFO[..4]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContinuityError::MessageTruncated("Nearby Info", 5, 2)
+        ));
+
+        let err = NearbyInfo::find(&[0x10]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContinuityError::MessageTruncated("header", 2, 1)
+        ));
+
+        let err = NearbyInfo::find(&[0x10, 0x02, 0x01, 0x98]).unwrap_err();
+        assert!(matches!(
+            err,
+            ContinuityError::MessageTooShort("Nearby Info", 5, 2)
+        ));
+    }
+
+    #[test]
+    fn truncated_trailing_message_is_tolerated() {
+        let data = [NEARBY_INFO.as_slice(), &HANDOFF[..6]].concat();
+        assert!(NearbyInfo::find(&data).unwrap().is_some());
+
+        // The iterator itself stops after the malformed message
+        let messages: Vec<_> = ContinuityMessages::new(&data).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].is_err());
+    }
+}
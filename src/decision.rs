@@ -0,0 +1,426 @@
+use crate::lib::irk::Irk;
+use crate::lib::rssi::RssiFilter;
+use crate::lib::scanner::Scanner;
+use crate::lib::watch::{AppleWatch, AppleWatchError, AppleWatchStatus};
+
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+use std::fmt::Display;
+#[cfg(not(feature = "cli"))]
+use std::fs::OpenOptions;
+#[cfg(not(feature = "cli"))]
+use std::io::Write;
+#[cfg(not(feature = "cli"))]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(not(feature = "cli"))]
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// Specifies the permissions of a newly created decision log, it
+/// names users and their watches so is only readable by root.
+#[cfg(not(feature = "cli"))]
+const DECISION_LOG_MODE: u32 = 0o600;
+
+/// The outcome of checking if an Apple Watch may unlock this device,
+/// along with every input and check that led to it, so admins can
+/// audit why each unlock succeeded or failed.
+#[derive(Debug, Serialize)]
+pub struct UnlockDecision {
+    /// Specifies when the check started, in seconds since the Unix epoch.
+    pub timestamp: u64,
+
+    pub user: String,
+    pub label: Option<String>,
+
+    /// Specifies if the watch was found by any of the searches.
+    pub found: bool,
+
+    /// Specifies how many searches it took to find the watch.
+    pub tries: Option<u8>,
+
+    /// Specifies the RSSI samples the filtered RSSI was computed from,
+    /// only the RSSI of the advertisement the watch was found with
+    /// unless more than one sample is collected.
+    pub rssi_samples: Vec<i16>,
+
+    #[serde(serialize_with = "serialize_display")]
+    pub rssi_filter: RssiFilter,
+
+    /// Specifies the filtered RSSI compared against the threshold.
+    pub rssi: Option<i16>,
+
+    pub unlock_threshold: i16,
+    pub locked: Option<bool>,
+    pub auto_unlock_enabled: Option<bool>,
+    pub require_auto_unlock: bool,
+
+    /// Specifies if the watch is allowed to unlock this device, which
+    /// is the case when there is no [`UnlockDecision::denial`].
+    pub unlocked: bool,
+
+    pub denial: Option<DenialReason>,
+
+    /// Specifies how long, in milliseconds, the check took.
+    pub elapsed_ms: u128,
+
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl UnlockDecision {
+    /// Starts recording the decision for a user's watch.
+    pub fn new(
+        user: &str,
+        label: Option<&str>,
+        rssi_filter: RssiFilter,
+        unlock_threshold: i16,
+        require_auto_unlock: bool,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            user: user.to_string(),
+            label: label.map(ToString::to_string),
+            found: false,
+            tries: None,
+            rssi_samples: Vec::new(),
+            rssi_filter,
+            rssi: None,
+            unlock_threshold,
+            locked: None,
+            auto_unlock_enabled: None,
+            require_auto_unlock,
+            unlocked: false,
+            denial: None,
+            elapsed_ms: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Searches for the watch using the scanner and checks if it may
+    /// unlock this device, finishing the decision.
+    ///
+    /// Each step is recorded in the decision as soon as it is taken, so
+    /// a check that is cancelled still records how far it got. The error
+    /// that stopped the watch being checked, if any, is returned.
+    pub async fn check<S: Scanner>(
+        decision: &RefCell<Self>,
+        adapter: &S,
+        irk: Irk,
+        retries: u8,
+        retry_timeout: Duration,
+        rssi_samples: u8,
+        rssi_window: Duration,
+    ) -> Result<(), AppleWatchError> {
+        let mut watch = AppleWatch::new(irk);
+
+        match watch.find_watch(adapter, retries, retry_timeout).await {
+            Ok(tries) => {
+                let mut decision = decision.borrow_mut();
+                decision.found = true;
+                decision.tries = Some(tries);
+            }
+            Err(err) => {
+                decision.borrow_mut().finish(Some(DenialReason::NotFound));
+                return Err(err);
+            }
+        }
+
+        let mut status = match watch.get_watch_status().await {
+            Ok(status) => status,
+            Err(err) => {
+                decision
+                    .borrow_mut()
+                    .finish(Some(DenialReason::StatusUnavailable));
+                return Err(err);
+            }
+        };
+
+        decision.borrow_mut().rssi_samples.push(status.rssi);
+        if rssi_samples > 1 {
+            match watch.sample_rssi(adapter, rssi_samples, rssi_window).await {
+                Ok(samples) => {
+                    let mut decision = decision.borrow_mut();
+                    status.rssi = decision.rssi_filter.apply(&samples).unwrap_or(status.rssi);
+                    decision.rssi_samples = samples;
+                }
+                Err(err) => {
+                    decision
+                        .borrow_mut()
+                        .finish(Some(DenialReason::RssiUnavailable));
+                    return Err(err);
+                }
+            }
+        }
+
+        let mut decision = decision.borrow_mut();
+        decision.rssi = Some(status.rssi);
+        decision.locked = Some(status.locked);
+        decision.auto_unlock_enabled = Some(status.device_auto_unlock_enabled);
+
+        let denial = decision.denial_reason(&status);
+        decision.finish(denial);
+        Ok(())
+    }
+
+    /// Returns the reason the watch isn't allowed to unlock
+    /// this device, if any.
+    fn denial_reason(&self, status: &AppleWatchStatus) -> Option<DenialReason> {
+        if status.rssi < self.unlock_threshold {
+            Some(DenialReason::TooFarAway)
+        } else if status.locked {
+            Some(DenialReason::Locked)
+        } else if self.require_auto_unlock && !status.device_auto_unlock_enabled {
+            Some(DenialReason::AutoUnlockDisabled)
+        } else {
+            None
+        }
+    }
+
+    /// Finishes the decision, denying the unlock for the supplied
+    /// reason, if any, and recording how long the check took.
+    pub fn finish(&mut self, denial: Option<DenialReason>) {
+        self.unlocked = denial.is_none();
+        self.denial = denial;
+        self.elapsed_ms = self.started.elapsed().as_millis();
+    }
+
+    /// Returns true if the decision has been made, a check that is
+    /// neither unlocked nor denied is still in progress.
+    #[cfg(not(feature = "cli"))]
+    pub fn is_finished(&self) -> bool {
+        self.unlocked || self.denial.is_some()
+    }
+
+    /// Serialises the decision as a single line of JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Appends the decision, as a line of JSON, to the decision log,
+    /// creating it if it doesn't exist.
+    #[cfg(not(feature = "cli"))]
+    pub fn append_to(&self, path: &Path) -> std::io::Result<()> {
+        let mut decision_log = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(DECISION_LOG_MODE)
+            .open(path)?;
+
+        writeln!(decision_log, "{}", self.to_json())
+    }
+}
+
+/// Identifies why a watch wasn't allowed to unlock this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DenialReason {
+    NotFound,
+    StatusUnavailable,
+    RssiUnavailable,
+    TooFarAway,
+    Locked,
+    AutoUnlockDisabled,
+
+    /// The maximum wait was reached before the checks finished.
+    Timeout,
+}
+
+impl DenialReason {
+    /// Returns the reason in the form shown to the user,
+    /// following the name of the watch.
+    pub fn message(self) -> &'static str {
+        match self {
+            Self::NotFound | Self::StatusUnavailable | Self::RssiUnavailable => "not available",
+            Self::TooFarAway => "is too far away",
+            Self::Locked => "is locked",
+            Self::AutoUnlockDisabled => "is not configured to auto-unlock devices",
+            Self::Timeout => "was not checked before the maximum wait",
+        }
+    }
+}
+
+/// Serialises a value using its [`Display`] implementation.
+fn serialize_display<T: Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DenialReason, UnlockDecision};
+    use crate::lib::irk::Irk;
+    use crate::lib::rssi::RssiFilter;
+    use crate::lib::scanner::mock::{MockAdvertisement, MockScanner};
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// Specifies an IRK and an address that resolves with it.
+    const WATCH_IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+    const WATCH_ADDRESS: &str = "4A:11:22:95:3D:A2";
+
+    /// Specifies the data flags of a Nearby Info message.
+    const LOCKED: u8 = 0x20;
+    const AUTO_UNLOCK_ENABLED: u8 = 0x80;
+
+    /// Returns an advertisement of the watch, with a Nearby
+    /// Info message carrying the data flags.
+    fn advertisement(address: &str, rssi: i16, data_flags: u8) -> MockAdvertisement {
+        MockAdvertisement {
+            delay: Duration::ZERO,
+            address: address.parse().unwrap(),
+            rssi: Some(rssi),
+            tx_power: None,
+            manufacturer_data: HashMap::from([(
+                0x004c,
+                vec![0x10, 0x05, 0x01, data_flags, 0xAA, 0xBB, 0xCC],
+            )]),
+        }
+    }
+
+    /// Checks the watch using the scanner, returning the
+    /// finished decision.
+    fn check(scanner: &MockScanner, require_auto_unlock: bool) -> UnlockDecision {
+        let decision = RefCell::new(UnlockDecision::new(
+            "test",
+            None,
+            RssiFilter::Median,
+            -80,
+            require_auto_unlock,
+        ));
+
+        let irk: Irk = WATCH_IRK.parse().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Only whether the watch was checked matters, the
+        // decision records why it was denied.
+        let _ = runtime.block_on(UnlockDecision::check(
+            &decision,
+            scanner,
+            irk,
+            1,
+            Duration::from_millis(100),
+            1,
+            Duration::ZERO,
+        ));
+
+        decision.into_inner()
+    }
+
+    #[test]
+    fn unlocked_watch_in_range_unlocks() {
+        let decision = check(
+            &MockScanner::new(vec![advertisement(WATCH_ADDRESS, -60, AUTO_UNLOCK_ENABLED)]),
+            true,
+        );
+
+        assert!(decision.found);
+        assert_eq!(decision.tries, Some(1));
+        assert_eq!(decision.rssi, Some(-60));
+        assert_eq!(decision.denial, None);
+        assert!(decision.unlocked);
+    }
+
+    #[test]
+    fn locked_watch_is_denied() {
+        let decision = check(
+            &MockScanner::new(vec![advertisement(
+                WATCH_ADDRESS,
+                -60,
+                AUTO_UNLOCK_ENABLED | LOCKED,
+            )]),
+            true,
+        );
+
+        assert_eq!(decision.locked, Some(true));
+        assert_eq!(decision.denial, Some(DenialReason::Locked));
+        assert!(!decision.unlocked);
+    }
+
+    #[test]
+    fn watch_below_threshold_is_too_far_away() {
+        let decision = check(
+            &MockScanner::new(vec![advertisement(WATCH_ADDRESS, -90, AUTO_UNLOCK_ENABLED)]),
+            true,
+        );
+
+        assert_eq!(decision.rssi, Some(-90));
+        assert_eq!(decision.denial, Some(DenialReason::TooFarAway));
+    }
+
+    #[test]
+    fn watch_is_not_found_without_power() {
+        let scanner =
+            MockScanner::new(vec![advertisement(WATCH_ADDRESS, -60, AUTO_UNLOCK_ENABLED)]);
+
+        let decision = check(&scanner.without_power(), true);
+        assert!(!decision.found);
+        assert_eq!(decision.denial, Some(DenialReason::NotFound));
+    }
+
+    #[test]
+    fn other_devices_are_not_the_watch() {
+        let decision = check(
+            &MockScanner::new(vec![advertisement(
+                "4A:11:22:95:3D:A3",
+                -60,
+                AUTO_UNLOCK_ENABLED,
+            )]),
+            true,
+        );
+
+        assert!(!decision.found);
+        assert_eq!(decision.denial, Some(DenialReason::NotFound));
+    }
+
+    #[test]
+    fn auto_unlock_is_required_unless_disabled() {
+        let decision = check(
+            &MockScanner::new(vec![advertisement(WATCH_ADDRESS, -60, 0)]),
+            true,
+        );
+        assert_eq!(decision.auto_unlock_enabled, Some(false));
+        assert_eq!(decision.denial, Some(DenialReason::AutoUnlockDisabled));
+
+        let decision = check(
+            &MockScanner::new(vec![advertisement(WATCH_ADDRESS, -60, 0)]),
+            false,
+        );
+        assert!(decision.unlocked);
+    }
+
+    #[test]
+    #[cfg(not(feature = "cli"))]
+    fn timed_out_decision_is_denied() {
+        let mut decision = UnlockDecision::new("test", Some("work"), RssiFilter::Median, -80, true);
+        assert!(!decision.is_finished());
+
+        decision.finish(Some(DenialReason::Timeout));
+        assert!(decision.is_finished());
+        assert!(!decision.unlocked);
+
+        let record: serde_json::Value = serde_json::from_str(&decision.to_json()).unwrap();
+        assert_eq!(record["denial"], "timeout");
+        assert_eq!(record["label"], "work");
+        assert_eq!(record["unlocked"], false);
+    }
+
+    #[test]
+    #[cfg(not(feature = "cli"))]
+    fn unlocked_decision_is_finished() {
+        let mut decision = UnlockDecision::new("test", None, RssiFilter::Median, -80, true);
+        decision.finish(None);
+        assert!(decision.is_finished());
+        assert!(decision.unlocked);
+    }
+}
@@ -0,0 +1,34 @@
+pub mod bluez;
+pub mod keychain;
+
+use crate::lib::irk::{Irk, IrkError};
+
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// A device, and its Identity Resolution Key, found in the key
+/// store of another Bluetooth stack that can be added to the config.
+#[derive(Debug, Clone)]
+pub struct ImportedDevice {
+    /// Specifies where the device was found in the key store,
+    /// e.g. the adapter and device addresses for BlueZ or the
+    /// key of the device in a macOS keychain export.
+    pub source: String,
+
+    /// Specifies the name of the device, if the key store has one.
+    pub name: Option<String>,
+
+    pub irk: Irk,
+}
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("{path} has an invalid IRK: {1}", path = .0.display())]
+    InvalidIrk(PathBuf, IrkError),
+
+    #[error("{path} is not a valid keychain export: {1}", path = .0.display())]
+    InvalidKeychain(PathBuf, String),
+}
@@ -0,0 +1,182 @@
+use crate::lib::import::ImportError::InvalidIrk;
+use crate::lib::import::{ImportError, ImportedDevice};
+use crate::lib::irk::Irk;
+
+use bluer::Address;
+use std::path::Path;
+
+/// Specifies the directory BlueZ stores its pairing database in, it
+/// contains a directory for each adapter which in turn contains a
+/// directory for each paired device.
+pub const STORAGE_DIR: &str = "/var/lib/bluetooth";
+
+/// Specifies the name of the keyfile, within each device directory,
+/// that holds the name and keys of the device.
+const INFO_FILE: &str = "info";
+
+const GENERAL_SECTION: &str = "General";
+const IRK_SECTION: &str = "IdentityResolvingKey";
+
+/// Finds the devices with an Identity Resolution Key in a BlueZ
+/// pairing database, the path may be either the storage directory
+/// or the directory of a single adapter.
+///
+/// BlueZ writes the key as hex, least significant byte first, which
+/// is the default byte order of [`Irk`]. A device with an invalid key
+/// is added to `skipped` rather than failing the import.
+pub fn load_devices(
+    path: &Path,
+    skipped: &mut Vec<ImportError>,
+) -> Result<Vec<ImportedDevice>, ImportError> {
+    let mut devices: Vec<ImportedDevice> = Vec::new();
+
+    let mut dir_entries = std::fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+    dir_entries.sort_by_key(std::fs::DirEntry::file_name);
+
+    for dir_entry in dir_entries {
+        let dir_path = dir_entry.path();
+        let Some(address) = dir_entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<Address>().ok())
+        else {
+            continue;
+        };
+
+        if !dir_path.is_dir() {
+            continue;
+        }
+
+        let info_path = dir_path.join(INFO_FILE);
+        if !info_path.is_file() {
+            // Directories named after an address without a keyfile
+            // are adapters, which contain the device directories.
+            devices.extend(load_devices(&dir_path, skipped)?);
+            continue;
+        }
+
+        let info = std::fs::read_to_string(&info_path)?;
+        let Some(encoded_irk) = keyfile_value(&info, IRK_SECTION, "Key") else {
+            continue;
+        };
+
+        let irk: Irk = match encoded_irk.parse() {
+            Ok(irk) => irk,
+            Err(err) => {
+                skipped.push(InvalidIrk(info_path, err));
+                continue;
+            }
+        };
+
+        let adapter = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        devices.push(ImportedDevice {
+            source: format!("{adapter}/{address}"),
+            name: keyfile_value(&info, GENERAL_SECTION, "Name").map(ToString::to_string),
+            irk,
+        });
+    }
+
+    Ok(devices)
+}
+
+/// Returns the value of a key within a section of a keyfile, the
+/// INI-like format BlueZ uses for its storage.
+fn keyfile_value<'a>(keyfile: &'a str, section: &str, key: &str) -> Option<&'a str> {
+    let mut in_section = false;
+
+    for line in keyfile.lines().map(str::trim) {
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            in_section = name == section;
+            continue;
+        }
+
+        if !in_section {
+            continue;
+        }
+
+        if let Some((line_key, value)) = line.split_once('=')
+            && line_key.trim() == key
+        {
+            return Some(value.trim());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{keyfile_value, load_devices};
+    use crate::lib::import::ImportError;
+    use crate::lib::irk::Irk;
+
+    use std::path::{Path, PathBuf};
+
+    /// Returns the path of a BlueZ pairing database fixture.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/bluez")
+            .join(name)
+    }
+
+    #[test]
+    fn devices_with_an_irk_are_imported() {
+        let mut skipped = Vec::new();
+        let devices = load_devices(&fixture("valid"), &mut skipped).unwrap();
+
+        // The headphones only have a link key, so aren't imported
+        assert_eq!(devices.len(), 1);
+        assert!(skipped.is_empty());
+        assert_eq!(devices[0].source, "00:1A:7D:DA:71:13/F0:99:B6:3D:A2:11");
+        assert_eq!(devices[0].name.as_deref(), Some("Apple Watch"));
+        assert_eq!(
+            devices[0].irk,
+            "XkVgPxNEK0p4TDgZegzDUA==".parse::<Irk>().unwrap()
+        );
+    }
+
+    #[test]
+    fn adapter_directory_is_imported() {
+        let devices = load_devices(&fixture("valid/00:1A:7D:DA:71:13"), &mut Vec::new()).unwrap();
+        assert_eq!(devices.len(), 1);
+    }
+
+    #[test]
+    fn malformed_irk_is_skipped() {
+        let mut skipped = Vec::new();
+        let devices = load_devices(&fixture("malformed"), &mut skipped).unwrap();
+
+        // The watch after the device with the malformed key is still imported
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].source, "00:1A:7D:DA:71:13/F0:99:B6:3D:A2:11");
+
+        assert_eq!(skipped.len(), 1);
+        assert!(
+            matches!(&skipped[0], ImportError::InvalidIrk(path, _) if path.ends_with("E4:2B:34:10:20:30/info"))
+        );
+    }
+
+    #[test]
+    fn missing_directory_is_rejected() {
+        let err = load_devices(&fixture("missing"), &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, ImportError::IOError(_)));
+    }
+
+    #[test]
+    fn keyfile_values_are_read_from_their_section() {
+        let keyfile = "[LinkKey]\nKey=AA\n\n[IdentityResolvingKey]\n Key = BB \n";
+        assert_eq!(
+            keyfile_value(keyfile, "IdentityResolvingKey", "Key"),
+            Some("BB")
+        );
+        assert_eq!(keyfile_value(keyfile, "General", "Name"), None);
+        assert_eq!(keyfile_value("Key=AA", "LinkKey", "Key"), None);
+    }
+}
@@ -0,0 +1,424 @@
+use crate::lib::import::ImportError::{InvalidIrk, InvalidKeychain};
+use crate::lib::import::{ImportError, ImportedDevice};
+use crate::lib::irk::{self, IRK_LENGTH, Irk, IrkByteOrder, IrkError};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::path::Path;
+
+/// Specifies the key that macOS stores the Identity Resolution Key
+/// of a paired device under.
+const IRK_KEY: &str = "Remote IRK";
+
+/// Specifies the keys, in order of preference, that may hold the
+/// name of a paired device alongside its key.
+const NAME_KEYS: [&str; 3] = ["Name", "Remote Name", "DeviceName"];
+
+/// Specifies the magic bytes at the start of a binary property list.
+const BINARY_PLIST_MAGIC: &[u8] = b"bplist";
+
+/// A value of an XML property list, the scalars that are never
+/// used to find a device are not kept.
+enum PlistValue {
+    Dict(Vec<(String, PlistValue)>),
+    Array(Vec<PlistValue>),
+    String(String),
+    Data(Vec<u8>),
+    Other,
+}
+
+/// A tag of an XML property list.
+struct Tag<'a> {
+    name: &'a str,
+    closing: bool,
+    empty: bool,
+}
+
+/// A minimal parser for the XML property lists exported from the macOS
+/// keychain, it supports only the elements of the plist DTD.
+struct PlistParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> PlistParser<'a> {
+    /// Skips whitespace, the XML declaration, the doctype and comments.
+    fn skip_misc(&mut self) {
+        loop {
+            self.rest = self.rest.trim_start();
+
+            let end = if self.rest.starts_with("<?") {
+                self.rest.find("?>").map(|end| end + 2)
+            } else if self.rest.starts_with("<!--") {
+                self.rest.find("-->").map(|end| end + 3)
+            } else if self.rest.starts_with("<!") {
+                self.rest.find('>').map(|end| end + 1)
+            } else {
+                return;
+            };
+
+            self.rest = &self.rest[end.unwrap_or(self.rest.len())..];
+        }
+    }
+
+    /// Reads the next tag, skipping anything that isn't an element.
+    fn next_tag(&mut self) -> Result<Tag<'a>, String> {
+        self.skip_misc();
+
+        let Some(rest) = self.rest.strip_prefix('<') else {
+            return Err(format!(
+                "expected a tag, found '{}'",
+                Self::excerpt(self.rest)
+            ));
+        };
+
+        let Some((tag, rest)) = rest.split_once('>') else {
+            return Err("unterminated tag".to_string());
+        };
+        self.rest = rest;
+
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let (empty, tag) = match tag.strip_suffix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+
+        // Attributes, such as the plist version, are not needed.
+        let name = tag.split_whitespace().next().unwrap_or_default();
+        Ok(Tag {
+            name,
+            closing,
+            empty,
+        })
+    }
+
+    /// Reads the text content of an element up to its closing tag.
+    fn text(&mut self, tag: &Tag) -> Result<String, String> {
+        if tag.empty {
+            return Ok(String::new());
+        }
+
+        let closing = format!("</{}>", tag.name);
+        let Some((text, rest)) = self.rest.split_once(&closing) else {
+            return Err(format!("missing {closing}"));
+        };
+        self.rest = rest;
+
+        Ok(text
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"))
+    }
+
+    /// Reads the value that starts with the given tag.
+    fn value(&mut self, tag: &Tag) -> Result<PlistValue, String> {
+        match tag.name {
+            "plist" => {
+                let value = self.next_tag()?;
+                let value = self.value(&value)?;
+                self.expect_closing("plist")?;
+                Ok(value)
+            }
+
+            "dict" => {
+                let mut entries = Vec::new();
+                if tag.empty {
+                    return Ok(PlistValue::Dict(entries));
+                }
+
+                loop {
+                    let key = self.next_tag()?;
+                    if key.closing && key.name == "dict" {
+                        return Ok(PlistValue::Dict(entries));
+                    }
+
+                    if key.name != "key" {
+                        return Err(format!("expected <key> in <dict>, found <{}>", key.name));
+                    }
+
+                    let key = self.text(&key)?;
+                    let value = self.next_tag()?;
+                    entries.push((key, self.value(&value)?));
+                }
+            }
+
+            "array" => {
+                let mut values = Vec::new();
+                if tag.empty {
+                    return Ok(PlistValue::Array(values));
+                }
+
+                loop {
+                    let value = self.next_tag()?;
+                    if value.closing && value.name == "array" {
+                        return Ok(PlistValue::Array(values));
+                    }
+
+                    values.push(self.value(&value)?);
+                }
+            }
+
+            "string" => Ok(PlistValue::String(self.text(tag)?)),
+
+            "data" => {
+                let encoded: String = self
+                    .text(tag)?
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect();
+
+                STANDARD
+                    .decode(encoded)
+                    .map(PlistValue::Data)
+                    .map_err(|err| format!("<data> is not valid Base64: {err}"))
+            }
+
+            "integer" | "real" | "date" | "true" | "false" => {
+                self.text(tag)?;
+                Ok(PlistValue::Other)
+            }
+
+            other => Err(format!("unexpected <{other}>")),
+        }
+    }
+
+    /// Reads the closing tag of an element.
+    fn expect_closing(&mut self, name: &str) -> Result<(), String> {
+        let tag = self.next_tag()?;
+        if !tag.closing || tag.name != name {
+            return Err(format!("expected </{name}>, found <{}>", tag.name));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the start of the remaining input, to show where
+    /// parsing failed.
+    fn excerpt(s: &str) -> &str {
+        let end = s.char_indices().nth(20).map_or(s.len(), |(i, _)| i);
+        &s[..end]
+    }
+}
+
+/// Finds the devices with an Identity Resolution Key in a property list
+/// exported from the macOS keychain.
+///
+/// The file may be the XML property list itself, or the hex encoding of
+/// it printed by `security find-generic-password -w`. Binary property
+/// lists must first be converted with `plutil -convert xml1`.
+pub fn load_devices(path: &Path) -> Result<Vec<ImportedDevice>, ImportError> {
+    let invalid = |reason: String| InvalidKeychain(path.to_path_buf(), reason);
+
+    let mut contents = std::fs::read(path)?;
+    if let Some(decoded) = decode_hex(&contents) {
+        contents = decoded;
+    }
+
+    if contents.starts_with(BINARY_PLIST_MAGIC) {
+        return Err(invalid(
+            "binary property lists are not supported, convert it with `plutil -convert xml1`"
+                .to_string(),
+        ));
+    }
+
+    let contents = String::from_utf8(contents).map_err(|_| invalid("not UTF-8".to_string()))?;
+
+    let mut parser = PlistParser { rest: &contents };
+    let root = parser.next_tag().map_err(invalid)?;
+    let root = parser.value(&root).map_err(invalid)?;
+
+    let mut devices = Vec::new();
+    find_devices(path, None, &root, &mut devices)?;
+    Ok(devices)
+}
+
+/// Walks the property list looking for dictionaries with a
+/// [`IRK_KEY`], which are the paired devices.
+fn find_devices(
+    path: &Path,
+    key: Option<&str>,
+    value: &PlistValue,
+    devices: &mut Vec<ImportedDevice>,
+) -> Result<(), ImportError> {
+    match value {
+        PlistValue::Dict(entries) => {
+            let get = |name: &str| {
+                entries
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value)
+            };
+
+            let irk = match get(IRK_KEY) {
+                Some(PlistValue::Data(bytes)) => Some(
+                    <[u8; IRK_LENGTH]>::try_from(bytes.as_slice())
+                        .map(|bytes| Irk::from_bytes(bytes, IrkByteOrder::LeastSignificantFirst))
+                        .map_err(|_| IrkError::InvalidLength(bytes.len())),
+                ),
+                Some(PlistValue::String(encoded)) => Some(encoded.parse::<Irk>()),
+                _ => None,
+            };
+
+            if let Some(irk) = irk {
+                let name = NAME_KEYS.iter().find_map(|name| match get(name) {
+                    Some(PlistValue::String(name)) => Some(name.clone()),
+                    _ => None,
+                });
+
+                devices.push(ImportedDevice {
+                    source: key.map_or_else(
+                        || format!("device {}", devices.len() + 1),
+                        ToString::to_string,
+                    ),
+                    name,
+                    irk: irk.map_err(|err| InvalidIrk(path.to_path_buf(), err))?,
+                });
+            }
+
+            for (key, value) in entries {
+                find_devices(path, Some(key), value, devices)?;
+            }
+        }
+
+        PlistValue::Array(values) => {
+            for value in values {
+                find_devices(path, key, value, devices)?;
+            }
+        }
+
+        PlistValue::String(_) | PlistValue::Data(_) | PlistValue::Other => {}
+    }
+
+    Ok(())
+}
+
+/// Decodes the contents of the file if it is entirely hex digits,
+/// ignoring surrounding whitespace.
+fn decode_hex(contents: &[u8]) -> Option<Vec<u8>> {
+    let digits = std::str::from_utf8(contents).ok()?.trim_ascii();
+    if digits.is_empty() {
+        return None;
+    }
+
+    irk::decode_hex(digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_hex, load_devices};
+    use crate::lib::import::ImportError;
+    use crate::lib::irk::Irk;
+
+    use std::fmt::Write;
+    use std::path::{Path, PathBuf};
+
+    /// Specifies the keychain export fixture.
+    const EXPORT: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/keychain/export.plist"
+    );
+
+    /// Writes the contents to a file in the temporary directory.
+    fn export(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "apple_watch-keychain-{name}-{}.plist",
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn plist(device: &str) -> String {
+        format!(r#"<?xml version="1.0"?><plist version="1.0"><dict>{device}</dict></plist>"#)
+    }
+
+    #[test]
+    fn devices_with_an_irk_are_imported() {
+        let devices = load_devices(Path::new(EXPORT)).unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].source, "4a:11:22:95:3d:a2");
+        assert_eq!(devices[0].name.as_deref(), Some("Alex's Apple Watch"));
+        assert_eq!(
+            devices[0].irk,
+            "XkVgPxNEK0p4TDgZegzDUA==".parse::<Irk>().unwrap()
+        );
+    }
+
+    #[test]
+    fn hex_encoded_export_is_imported() {
+        let contents = std::fs::read(EXPORT).unwrap();
+        let hex = contents.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+
+        let path = export("hex", format!("{hex}\n"));
+        let devices = load_devices(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name.as_deref(), Some("Alex's Apple Watch"));
+    }
+
+    #[test]
+    fn hex_is_only_detected_when_every_character_is_hex() {
+        assert_eq!(decode_hex(b" 3c706c \n"), Some(b"<pl".to_vec()));
+        assert_eq!(decode_hex(b"3c706"), None);
+        assert_eq!(decode_hex(b"<plist>"), None);
+        assert_eq!(decode_hex(b""), None);
+    }
+
+    #[test]
+    fn irk_string_is_imported() {
+        let path = export(
+            "string",
+            plist(
+                "<key>watch</key><dict><key>Remote IRK</key><string>msb:ec0234a357c8ad05341010a60a397d9b</string></dict>",
+            ),
+        );
+        let devices = load_devices(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(devices[0].source, "watch");
+        assert_eq!(devices[0].name, None);
+        assert_eq!(
+            devices[0].irk,
+            "m305CqYQEDQFrchXozQC7A==".parse::<Irk>().unwrap()
+        );
+    }
+
+    #[test]
+    fn wrong_length_irk_is_rejected() {
+        let path = export(
+            "length",
+            plist("<key>watch</key><dict><key>Remote IRK</key><data>XkVgPxNEK0p4</data></dict>"),
+        );
+        let err = load_devices(&path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(matches!(err, ImportError::InvalidIrk(..)));
+    }
+
+    #[test]
+    fn binary_and_malformed_exports_are_rejected() {
+        for (name, contents) in [
+            ("binary", "bplist00\u{1}\u{2}".to_string()),
+            ("unterminated", plist("<key>watch</key><dict>")),
+            ("unknown", plist("<key>watch</key><blob/>")),
+        ] {
+            let path = export(name, contents);
+            let err = load_devices(&path).unwrap_err();
+            std::fs::remove_file(path).unwrap();
+
+            assert!(
+                matches!(err, ImportError::InvalidKeychain(..)),
+                "{name}: {err}"
+            );
+        }
+    }
+}
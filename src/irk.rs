@@ -0,0 +1,379 @@
+use crate::lib::irk::IrkError::{InvalidHex, InvalidLength};
+
+use aes::Aes128;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use bluer::Address;
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Specifies the length, in bytes, of an Identity Resolution Key.
+pub const IRK_LENGTH: usize = 16;
+
+/// Specifies the length, in bytes, of the random part, `prand`, and
+/// of the hash of a resolvable private address.
+const PRAND_LENGTH: usize = 3;
+
+/// An Identity Resolution Key, which is used to resolve the random
+/// private Bluetooth addresses of a device back to that device.
+///
+/// An IRK can be written in any of the following encodings:
+///   * Base64, e.g. `XkVgPxNEK0p4TDgZegzDUA==`
+///   * Hex, e.g. `5e45603f13442b4a784c38197a0cc350`, optionally prefixed with `0x`
+///   * Colon separated hex, e.g. `5e:45:60:3f:13:44:2b:4a:78:4c:38:19:7a:0c:c3:50`
+///
+/// By default the bytes are read least significant byte first, which is
+/// how macOS stores the key, an explicit byte order can be given with a
+/// `lsb:` or `msb:` prefix, e.g. `msb:50c30c7a19384c784a2b44133f60455e`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Irk {
+    /// Specifies the key, most significant byte first, as it is
+    /// used with AES-128 to resolve addresses.
+    key: [u8; IRK_LENGTH],
+}
+
+impl Irk {
+    /// Creates an [`Irk`] from the raw bytes of the key in
+    /// the given byte order.
+    pub fn from_bytes(mut bytes: [u8; IRK_LENGTH], byte_order: IrkByteOrder) -> Self {
+        if byte_order == IrkByteOrder::LeastSignificantFirst {
+            bytes.reverse();
+        }
+
+        Self { key: bytes }
+    }
+
+    /// Returns the key that would have been read had the bytes been
+    /// written in the opposite order, used to detect keys that were
+    /// copied from a tool which uses the other byte order.
+    #[cfg(feature = "cli")]
+    pub fn reversed(self) -> Self {
+        Self::from_bytes(self.key, IrkByteOrder::LeastSignificantFirst)
+    }
+
+    /// Encodes the key as Base64, least significant byte first,
+    /// which is the canonical form used in the config.
+    #[cfg(feature = "cli")]
+    pub fn to_base64(self) -> String {
+        let mut bytes = self.key;
+        bytes.reverse();
+        STANDARD.encode(bytes)
+    }
+
+    /// Returns true if the address is a resolvable private address that
+    /// was generated from this key.
+    ///
+    /// Use an [`IrkResolver`] to test addresses against many keys, or the
+    /// same key many times, as it only expands each key once.
+    #[cfg(feature = "cli")]
+    pub fn resolves(self, address: Address) -> bool {
+        Self::resolves_with(&Aes128::new(&self.key.into()), address)
+    }
+
+    /// Returns true if the address is a resolvable private address that
+    /// was generated from the key the cipher was initialised with.
+    ///
+    /// The top two bits of a resolvable private address are `01`, the
+    /// remainder of the top 3 bytes are `prand` and the bottom 3 bytes
+    /// are the hash of `prand` by the random address hash function `ah`.
+    fn resolves_with(cipher: &Aes128, address: Address) -> bool {
+        if (address.0[0] >> 6) != 0b01 {
+            return false;
+        }
+
+        let (prand, hash) = address.0.split_at(PRAND_LENGTH);
+        hash == Self::ah(cipher, prand)
+    }
+
+    /// Computes the random address hash function `ah`, defined in the
+    /// Bluetooth Core Specification Vol 3, Part H, Section 2.2.2, which
+    /// is the bottom 3 bytes of `prand` padded with zeros to 16 bytes
+    /// and encrypted using AES-128 with the key.
+    fn ah(cipher: &Aes128, prand: &[u8]) -> [u8; PRAND_LENGTH] {
+        let mut block = [0; IRK_LENGTH];
+        block[IRK_LENGTH - PRAND_LENGTH..].copy_from_slice(prand);
+
+        let mut block = block.into();
+        cipher.encrypt_block(&mut block);
+
+        let mut hash = [0; PRAND_LENGTH];
+        hash.copy_from_slice(&block[IRK_LENGTH - PRAND_LENGTH..]);
+        hash
+    }
+
+    /// Decodes the bytes of a key written as hex, with or without
+    /// colons separating each byte.
+    fn decode_hex(encoded: &str) -> Result<Vec<u8>, IrkError> {
+        let digits: String = if encoded.contains(':') {
+            encoded
+                .split(':')
+                .map(|byte| match byte.len() {
+                    2 => Ok(byte),
+                    _ => Err(InvalidHex(byte.to_string())),
+                })
+                .collect::<Result<_, _>>()?
+        } else {
+            encoded.strip_prefix("0x").unwrap_or(encoded).to_string()
+        };
+
+        decode_hex(&digits).ok_or_else(|| InvalidHex(encoded.to_string()))
+    }
+
+    /// Returns true if the key looks to be written as hex rather than
+    /// Base64, a 16 byte key is 32 hex digits or 24 Base64 characters.
+    fn is_hex(encoded: &str) -> bool {
+        let digits = encoded.strip_prefix("0x").unwrap_or(encoded);
+        encoded.contains(':')
+            || (digits.len() == IRK_LENGTH * 2 && digits.chars().all(|c| c.is_ascii_hexdigit()))
+    }
+}
+
+impl FromStr for Irk {
+    type Err = IrkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (byte_order, encoded) = IrkByteOrder::split_prefix(s.trim());
+
+        let decoded = if Self::is_hex(encoded) {
+            Self::decode_hex(encoded)?
+        } else {
+            STANDARD.decode(encoded)?
+        };
+
+        let bytes: [u8; IRK_LENGTH] = decoded
+            .try_into()
+            .map_err(|decoded: Vec<u8>| InvalidLength(decoded.len()))?;
+
+        Ok(Self::from_bytes(bytes, byte_order))
+    }
+}
+
+/// Decodes bytes written as hex, two digits for each byte.
+pub fn decode_hex(digits: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` also accepts a leading sign, e.g. `+f`
+    if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Resolves addresses against a set of [`Irk`]s, the AES key schedule
+/// of each key is expanded once when the resolver is created rather
+/// than for every address.
+///
+/// The sample data of the Bluetooth Core Specification, Vol 3, Part H,
+/// Appendix D.7, can be used to check an implementation: the key
+/// `msb:ec0234a357c8ad05341010a60a397d9b` resolves the address
+/// `70:81:94:0D:FB:AA`, whose `prand` is `708194` and hash `0dfbaa`.
+pub struct IrkResolver {
+    keys: Vec<(Irk, Aes128)>,
+}
+
+impl IrkResolver {
+    /// Creates an [`IrkResolver`] for the supplied keys.
+    pub fn new(irks: impl IntoIterator<Item = Irk>) -> Self {
+        Self {
+            keys: irks
+                .into_iter()
+                .map(|irk| (irk, Aes128::new(&irk.key.into())))
+                .collect(),
+        }
+    }
+
+    /// Returns the first key, in the order supplied to [`IrkResolver::new`],
+    /// that resolves the address, if any.
+    pub fn resolve(&self, address: Address) -> Option<Irk> {
+        self.keys
+            .iter()
+            .find(|(_, cipher)| Irk::resolves_with(cipher, address))
+            .map(|(irk, _)| *irk)
+    }
+
+    /// Returns true if any of the keys resolves the address.
+    pub fn resolves(&self, address: Address) -> bool {
+        self.resolve(address).is_some()
+    }
+}
+
+/// Identifies the order in which the bytes of an [`Irk`] are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IrkByteOrder {
+    /// The least significant byte is written first, this is how macOS
+    /// stores the key and so how it appears in keychain exports.
+    #[default]
+    LeastSignificantFirst,
+
+    /// The most significant byte is written first, this is how the key
+    /// is written in the Bluetooth Core Specification.
+    MostSignificantFirst,
+}
+
+impl IrkByteOrder {
+    const LSB_PREFIX: &'static str = "lsb:";
+    const MSB_PREFIX: &'static str = "msb:";
+
+    /// Splits the byte order prefix, if any, from an encoded key.
+    fn split_prefix(s: &str) -> (Self, &str) {
+        if let Some(encoded) = s.strip_prefix(Self::MSB_PREFIX) {
+            (Self::MostSignificantFirst, encoded)
+        } else if let Some(encoded) = s.strip_prefix(Self::LSB_PREFIX) {
+            (Self::LeastSignificantFirst, encoded)
+        } else {
+            (Self::default(), s)
+        }
+    }
+}
+
+impl Display for IrkByteOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LeastSignificantFirst => write!(f, "least significant byte first"),
+            Self::MostSignificantFirst => write!(f, "most significant byte first"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum IrkError {
+    #[error("IRK is not valid Base64: {0}")]
+    InvalidBase64(#[from] base64::DecodeError),
+
+    #[error("IRK is not valid hex, at '{0}'")]
+    InvalidHex(String),
+
+    #[error("IRK must be {IRK_LENGTH} bytes long, got {0}")]
+    InvalidLength(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Specifies the key of the sample data in the Bluetooth Core
+    /// Specification, Vol 3, Part H, Appendix D.7.
+    const SPEC_IRK: &str = "msb:ec0234a357c8ad05341010a60a397d9b";
+
+    /// Specifies the address resolved by [`SPEC_IRK`] in the sample data.
+    const SPEC_ADDRESS: Address = Address::new([0x70, 0x81, 0x94, 0x0d, 0xfb, 0xaa]);
+
+    /// Specifies the key of the watch in the replay capture used to
+    /// reproduce issues, as exported from the macOS keychain.
+    const WATCH_IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+    const WATCH_ADDRESS: Address = Address::new([0x4a, 0x11, 0x22, 0x95, 0x3d, 0xa2]);
+
+    fn irk(encoded: &str) -> Irk {
+        encoded.parse().expect("valid IRK")
+    }
+
+    #[test]
+    fn ah_matches_spec_sample_data() {
+        let cipher = Aes128::new(&irk(SPEC_IRK).key.into());
+        assert_eq!(Irk::ah(&cipher, &[0x70, 0x81, 0x94]), [0x0d, 0xfb, 0xaa]);
+    }
+
+    #[test]
+    fn resolver_resolves_spec_address() {
+        let resolver = IrkResolver::new([irk(WATCH_IRK), irk(SPEC_IRK)]);
+        assert_eq!(resolver.resolve(SPEC_ADDRESS), Some(irk(SPEC_IRK)));
+        assert_eq!(resolver.resolve(WATCH_ADDRESS), Some(irk(WATCH_IRK)));
+    }
+
+    #[test]
+    fn resolver_rejects_other_addresses() {
+        let resolver = IrkResolver::new([irk(SPEC_IRK)]);
+
+        // Same prand with a different hash
+        assert!(!resolver.resolves(Address::new([0x70, 0x81, 0x94, 0x0d, 0xfb, 0xab])));
+
+        // Resolvable by another key
+        assert!(!resolver.resolves(WATCH_ADDRESS));
+
+        // Top bits aren't `01`, so it isn't a resolvable private address
+        let mut static_address = SPEC_ADDRESS;
+        static_address.0[0] |= 0xc0;
+        assert!(!resolver.resolves(static_address));
+
+        assert!(!IrkResolver::new([]).resolves(SPEC_ADDRESS));
+    }
+
+    #[test]
+    fn resolver_returns_first_matching_key() {
+        let resolver = IrkResolver::new([irk(SPEC_IRK), irk(SPEC_IRK)]);
+        assert_eq!(resolver.resolve(SPEC_ADDRESS), Some(irk(SPEC_IRK)));
+    }
+
+    #[test]
+    fn parses_every_encoding() {
+        let expected = irk(SPEC_IRK);
+        for encoded in [
+            "msb:ec0234a357c8ad05341010a60a397d9b",
+            "msb:0xec0234a357c8ad05341010a60a397d9b",
+            "msb:ec:02:34:a3:57:c8:ad:05:34:10:10:a6:0a:39:7d:9b",
+            "msb:7AI0o1fIrQU0EBCmCjl9mw==",
+            "9b7d390aa610103405adc857a33402ec",
+            "lsb:9b7d390aa610103405adc857a33402ec",
+            "9b:7d:39:0a:a6:10:10:34:05:ad:c8:57:a3:34:02:ec",
+            "m305CqYQEDQFrchXozQC7A==",
+            "lsb:m305CqYQEDQFrchXozQC7A==",
+            "  m305CqYQEDQFrchXozQC7A==\n",
+        ] {
+            assert_eq!(irk(encoded), expected, "{encoded}");
+        }
+    }
+
+    #[test]
+    fn byte_order_prefix_reverses_key() {
+        assert_ne!(
+            irk("lsb:ec0234a357c8ad05341010a60a397d9b"),
+            irk("msb:ec0234a357c8ad05341010a60a397d9b")
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        for (encoded, length) in [
+            ("XkVgPxNEK0p4TDgZegzD", 15),
+            ("XkVgPxNEK0p4TDgZegzDUAA=", 17),
+            ("ec:02:34", 3),
+            ("", 0),
+        ] {
+            assert!(
+                matches!(encoded.parse::<Irk>(), Err(InvalidLength(got)) if got == length),
+                "{encoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_encodings() {
+        assert!(matches!("ec:2:34".parse::<Irk>(), Err(InvalidHex(_))));
+        assert!(matches!("ec:zz:34".parse::<Irk>(), Err(InvalidHex(_))));
+        assert!(matches!("ec:+2:34".parse::<Irk>(), Err(InvalidHex(_))));
+        assert!(
+            "0x0x5e45603f13442b4a784c38197a0cc350"
+                .parse::<Irk>()
+                .is_err()
+        );
+        assert!(matches!(
+            "not base64!".parse::<Irk>(),
+            Err(IrkError::InvalidBase64(_))
+        ));
+    }
+
+    #[test]
+    fn decode_hex_only_accepts_hex_digits() {
+        assert_eq!(decode_hex("0aFf"), Some(vec![0x0a, 0xff]));
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("+f"), None);
+        assert_eq!(decode_hex("-1"), None);
+        assert_eq!(decode_hex("0x0a"), None);
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("éa"), None);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod args;
+#[cfg(feature = "cli")]
+pub mod capture;
 pub mod conf;
+pub mod continuity;
+pub mod decision;
+#[cfg(feature = "cli")]
+pub mod import;
+pub mod irk;
+pub mod nss;
+pub mod rssi;
+pub mod scanner;
 pub mod watch;
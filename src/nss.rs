@@ -0,0 +1,192 @@
+use std::ffi::{CString, c_char, c_int};
+use std::mem::MaybeUninit;
+use std::ptr;
+
+/// Specifies the initial size, in bytes, of the buffer that NSS
+/// writes the strings of a record into, it is doubled until the
+/// record fits.
+const INITIAL_BUFFER_SIZE: usize = 1024;
+
+/// Specifies the largest buffer that will be offered to NSS before
+/// the lookup is abandoned.
+const MAX_BUFFER_SIZE: usize = 1 << 20;
+
+/// Specifies the initial number of groups to request from
+/// `getgrouplist`, it is grown to the number of groups reported.
+const INITIAL_GROUP_COUNT: usize = 32;
+
+/// Calls a re-entrant NSS lookup function, growing the string buffer
+/// until the record fits, and returns the record if it was found.
+fn lookup<T>(lookup_fn: impl Fn(*mut T, *mut c_char, usize, *mut *mut T) -> c_int) -> Option<T> {
+    let mut buffer: Vec<c_char> = vec![0; INITIAL_BUFFER_SIZE];
+
+    loop {
+        let mut record = MaybeUninit::<T>::uninit();
+        let mut result: *mut T = ptr::null_mut();
+
+        let err = lookup_fn(
+            record.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &raw mut result,
+        );
+
+        if err == libc::ERANGE && buffer.len() < MAX_BUFFER_SIZE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+
+        if err != 0 || result.is_null() {
+            return None;
+        }
+
+        // SAFETY: NSS initialised the record as it reported success.
+        // Only plain values are read from the record, so the pointers
+        // into `buffer` are never used after it is dropped.
+        return Some(unsafe { record.assume_init() });
+    }
+}
+
+/// Returns the user ID and primary group ID of the user, if the
+/// user exists.
+fn user_ids(user: &CString) -> Option<(libc::uid_t, libc::gid_t)> {
+    // SAFETY: the name is NUL terminated, and `lookup` supplies a
+    // record, and a buffer of the given length, for NSS to write to.
+    lookup(|passwd, buffer, length, result| unsafe {
+        libc::getpwnam_r(user.as_ptr(), passwd, buffer, length, result)
+    })
+    .map(|passwd: libc::passwd| (passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Returns the primary group ID of the user, if the user exists.
+fn primary_group_id(user: &CString) -> Option<libc::gid_t> {
+    user_ids(user).map(|(_, gid)| gid)
+}
+
+/// Returns the ID of the group, if the group exists.
+fn group_id(group: &CString) -> Option<libc::gid_t> {
+    // SAFETY: the name is NUL terminated, and `lookup` supplies a
+    // record, and a buffer of the given length, for NSS to write to.
+    lookup(|entry, buffer, length, result| unsafe {
+        libc::getgrnam_r(group.as_ptr(), entry, buffer, length, result)
+    })
+    .map(|entry: libc::group| entry.gr_gid)
+}
+
+/// Returns true if the user exists according to NSS.
+#[cfg(feature = "cli")]
+pub fn user_exists(user: &str) -> bool {
+    CString::new(user).is_ok_and(|user| primary_group_id(&user).is_some())
+}
+
+/// Returns true if the group exists according to NSS.
+#[cfg(feature = "cli")]
+pub fn group_exists(group: &str) -> bool {
+    CString::new(group).is_ok_and(|group| group_id(&group).is_some())
+}
+
+/// Returns true if the user is a member of the group according to
+/// NSS, either as their primary group or as a supplementary group.
+pub fn user_in_group(user: &str, group: &str) -> bool {
+    let (Ok(user), Ok(group)) = (CString::new(user), CString::new(group)) else {
+        return false;
+    };
+
+    let (Some(primary_group_id), Some(group_id)) = (primary_group_id(&user), group_id(&group))
+    else {
+        return false;
+    };
+
+    group_list(&user, primary_group_id).contains(&group_id)
+}
+
+/// Returns the IDs of every group the user is a member of, including
+/// their primary group.
+fn group_list(user: &CString, primary_group_id: libc::gid_t) -> Vec<libc::gid_t> {
+    let mut groups: Vec<libc::gid_t> = vec![0; INITIAL_GROUP_COUNT];
+    loop {
+        let mut count = c_int::try_from(groups.len()).unwrap_or(c_int::MAX);
+
+        // SAFETY: `count` never exceeds the length of `groups`.
+        let found = unsafe {
+            libc::getgrouplist(
+                user.as_ptr(),
+                primary_group_id,
+                groups.as_mut_ptr(),
+                &raw mut count,
+            )
+        };
+
+        let count = usize::try_from(count).unwrap_or_default();
+        if found == -1 && count > groups.len() {
+            groups.resize(count, 0);
+            continue;
+        }
+
+        groups.truncate(count);
+        return groups;
+    }
+}
+
+/// The IDs a process runs with to act as a user.
+#[cfg(not(feature = "cli"))]
+pub struct Credentials {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+
+    /// Specifies the supplementary groups, including the primary group.
+    pub groups: Vec<libc::gid_t>,
+}
+
+/// Returns the credentials of the user according to NSS, if the
+/// user exists.
+#[cfg(not(feature = "cli"))]
+pub fn credentials(user: &str) -> Option<Credentials> {
+    let user = CString::new(user).ok()?;
+    let (uid, gid) = user_ids(&user)?;
+
+    Some(Credentials {
+        uid,
+        gid,
+        groups: group_list(&user, gid),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group_id, group_list, user_ids, user_in_group};
+
+    use std::ffi::CString;
+
+    /// Specifies a name that no user or group on a test system has.
+    const MISSING: &str = "pam-apple-watch-missing";
+
+    #[test]
+    fn root_is_looked_up() {
+        let root = CString::new("root").unwrap();
+
+        assert_eq!(user_ids(&root), Some((0, 0)));
+        assert!(group_list(&root, 0).contains(&0));
+    }
+
+    #[test]
+    fn root_group_is_gid_0() {
+        assert_eq!(group_id(&CString::new("root").unwrap()), Some(0));
+        assert!(user_in_group("root", "root"));
+    }
+
+    #[test]
+    fn missing_names_are_not_found() {
+        let missing = CString::new(MISSING).unwrap();
+
+        assert_eq!(user_ids(&missing), None);
+        assert_eq!(group_id(&missing), None);
+        assert!(!user_in_group(MISSING, "root"));
+        assert!(!user_in_group("root", MISSING));
+    }
+
+    #[test]
+    fn names_with_a_nul_are_not_members() {
+        assert!(!user_in_group("ro\0ot", "root"));
+    }
+}
@@ -0,0 +1,77 @@
+// TODO: Upstream this to pam
+
+use pam::{PamHandle, PamReturnCode};
+use std::ffi::{CStr, CString, c_int, c_void};
+use std::ptr;
+
+/// Frees a value stored by [`set`] once the PAM transaction ends,
+/// or the value is replaced.
+unsafe extern "C" fn free(_: *mut PamHandle, data: *mut c_void, _: c_int) {
+    // SAFETY: the data was created by `CString::into_raw` in `set`,
+    // and PAM calls the cleanup exactly once for each value.
+    drop(unsafe { CString::from_raw(data.cast()) });
+}
+
+/// Stores the value under the name for the rest of the PAM transaction,
+/// where it can be read by later PAM functions of this module.
+pub fn set(handle: &PamHandle, name: &CStr, value: &str) -> Result<(), PamReturnCode> {
+    let value = CString::new(value)
+        .map_err(|_| PamReturnCode::Buf_Err)?
+        .into_raw();
+
+    // SAFETY: the handle is valid for the length of the call, PAM only
+    // takes a mutable handle as `pam_set_data` predates `const`, and
+    // ownership of the value passes to PAM when the call succeeds.
+    let stored: PamReturnCode = unsafe {
+        pam::ffi::pam_set_data(
+            ptr::from_ref(handle).cast_mut(),
+            name.as_ptr(),
+            value.cast(),
+            Some(free),
+        )
+    }
+    .into();
+
+    if stored != PamReturnCode::Success {
+        // SAFETY: PAM didn't take ownership of the value.
+        drop(unsafe { CString::from_raw(value) });
+        return Err(stored);
+    }
+
+    Ok(())
+}
+
+/// Returns the value stored under the name by [`set`] earlier in the
+/// PAM transaction, if any.
+pub fn get(handle: &PamHandle, name: &CStr) -> Option<String> {
+    let mut data: *const c_void = ptr::null();
+
+    // SAFETY: the handle and name are valid for the length of the call,
+    // which writes a pointer to the stored value into `data`.
+    let found: PamReturnCode =
+        unsafe { pam::ffi::pam_get_data(handle, name.as_ptr(), &raw mut data) }.into();
+    if found != PamReturnCode::Success || data.is_null() {
+        return None;
+    }
+
+    // SAFETY: values are only stored by `set`, as NUL terminated strings
+    // that remain valid until the PAM transaction ends.
+    let value = unsafe { CStr::from_ptr(data.cast()) };
+    Some(value.to_string_lossy().into_owned())
+}
+
+/// Returns the variable from the PAM environment, which modules such
+/// as `pam_systemd` add to, if it is set.
+pub fn env(handle: &PamHandle, name: &CStr) -> Option<String> {
+    // SAFETY: the handle and name are valid for the length of the call,
+    // PAM only takes a mutable handle as `pam_getenv` predates `const`.
+    let value = unsafe { pam::ffi::pam_getenv(ptr::from_ref(handle).cast_mut(), name.as_ptr()) };
+    if value.is_null() {
+        return None;
+    }
+
+    // SAFETY: the value is a NUL terminated string owned by PAM, which
+    // is copied before the environment can be changed.
+    let value = unsafe { CStr::from_ptr(value) };
+    Some(value.to_string_lossy().into_owned())
+}
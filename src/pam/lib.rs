@@ -1,16 +1,25 @@
 mod conv;
+mod data;
 #[path = "../lib.rs"]
 mod lib;
+mod log;
+mod session;
 
-use crate::lib::watch::{AppleWatch, AppleWatchStatus};
+use crate::lib::scanner::Scanner;
 
 use crate::conv::ClientConv;
-use crate::lib::conf::Config;
-use base64::{engine::general_purpose::STANDARD, Engine as _};
+use crate::lib::args::ModuleArgs;
+use crate::lib::conf::{Config, Entry, EntryOptions};
+use crate::lib::decision::{DenialReason, UnlockDecision};
+use crate::lib::irk::Irk;
+use crate::log::Logger;
+use crate::session::SessionMonitor;
 use pam::{export_pam_module, get_user, PamHandle, PamModule, PamReturnCode};
-use std::collections::HashMap;
-use std::ffi::{c_uint, CStr};
-use std::time::Duration;
+use std::cell::RefCell;
+use std::ffi::{c_uint, CStr, CString};
+use std::fmt::Display;
+use std::time::{Duration, SystemTime};
+use tokio::time::timeout;
 
 struct AppleWatchPAM;
 export_pam_module!(AppleWatchPAM);
@@ -24,128 +33,624 @@ impl PamModule for AppleWatchPAM {
             return PamReturnCode::Service_Err;
         };
 
-        let args: Vec<_> = args.iter().map(|s| s.to_string_lossy()).collect();
+        let (args, mut log) = match Self::parse_args(handle, &args, "auth") {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+
+        let (user, config) = match Self::load_user(handle, &args, &mut log) {
+            Ok(loaded) => loaded,
+            Err(err) => return err,
+        };
+
+        let entries = config.get_user_entries(&user);
+        if entries.is_empty() {
+            log.debug("No config entry for user", &[]);
+            return PamReturnCode::Ignore;
+        }
 
-        let args: HashMap<&str, &str> = args
-            .iter()
-            .map(|s| {
-                let mut parts = s.splitn(2, '=');
-                (parts.next().unwrap(), parts.next().unwrap_or(""))
-            })
+        let now = SystemTime::now();
+        let entries: Vec<&Entry> = entries
+            .into_iter()
+            .filter(|entry| entry.options.is_active(now))
             .collect();
 
-        let config = match Config::load() {
-            Ok(config) => config,
+        if entries.is_empty() {
+            log.debug("Apple Watch unlock is disabled or expired for user", &[]);
+            return PamReturnCode::Ignore;
+        }
+
+        let conv = match ClientConv::try_from(handle) {
+            Ok(conv) => conv,
             Err(err) => {
-                eprintln!("Failed to get module config: {err}");
-                return PamReturnCode::No_Module_Data;
+                log.error("Unable to get pam_conv", &[("error", &err)]);
+                return err.0;
             }
         };
 
-        let user = match get_user(handle, None) {
+        let mut watches: Vec<(&Entry, Irk)> = Vec::new();
+        for entry in entries {
+            match entry.irk() {
+                Ok(irk) => watches.push((entry, irk)),
+                Err(err) => {
+                    log.error("Failed to decode IRK", &[("error", &err)]);
+                    return PamReturnCode::Bad_Item;
+                }
+            }
+        }
+
+        async_runtime.block_on(async {
+            let session = match bluer::Session::new().await {
+                Ok(session) => session,
+                Err(err) => {
+                    log.error("Failed to create Bluetooth session", &[("error", &err)]);
+                    return PamReturnCode::Service_Err;
+                }
+            };
+
+            conv.info(c"Searching for Apple Watch");
+
+            // Each decision outlives the search that makes it, so the
+            // watches still being searched for when the maximum wait is
+            // reached are recorded as having timed out.
+            let decisions: Vec<RefCell<UnlockDecision>> = watches
+                .iter()
+                .map(|(entry, _)| RefCell::new(Self::new_decision(&args, &user, &entry.options)))
+                .collect();
+
+            // Every watch is searched for at the same time, the first
+            // watch to pass all the checks cancels the remaining searches.
+            let searches = watches
+                .iter()
+                .zip(&decisions)
+                .map(|((entry, irk), decision)| {
+                    Box::pin(Self::unlock_with_entry(
+                        &session, &args, entry, decision, &conv, &log, *irk,
+                    ))
+                });
+
+            // The searches are cancelled once the maximum wait is
+            // reached, so a slow search can't hold up the login.
+            let search = futures::future::select_ok(searches);
+            let result = match args.max_wait {
+                Some(max_wait) => timeout(max_wait, search).await.unwrap_or_else(|_| {
+                    log.info(
+                        "Apple Watch search exceeded the maximum wait",
+                        &[("max_wait_ms", &max_wait.as_millis())],
+                    );
+
+                    for decision in &decisions {
+                        let mut decision = decision.borrow_mut();
+                        if !decision.is_finished() {
+                            decision.finish(Some(DenialReason::Timeout));
+                            let log = Self::watch_log(&log, decision.label.as_deref());
+                            Self::record_decision(&decision, &args, &log);
+                        }
+                    }
+
+                    conv.error(c"Apple Watch not available");
+                    Err(PamReturnCode::Ignore)
+                }),
+                None => search.await,
+            };
+
+            match result {
+                Ok((entry, _)) => {
+                    Self::set_authenticated(handle, entry, &log);
+                    conv.info(c"Unlocking with Apple Watch");
+                    PamReturnCode::Success
+                }
+                Err(err) => err,
+            }
+        })
+    }
+
+    fn account_management(handle: &PamHandle, args: Vec<&CStr>, _: c_uint) -> PamReturnCode {
+        let (args, mut log) = match Self::parse_args(handle, &args, "account") {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
+        };
+
+        let user = match Self::user(handle, &mut log) {
             Ok(user) => user,
-            Err(err) => {
-                eprintln!("Failed to get current user: {err}");
-                return err.0;
+            Err(err) => return err,
+        };
+
+        // A config that can't be loaded is ignored rather than denying
+        // every account, authentication already fails without it.
+        let Some(config) = Self::load_config(&args, &log) else {
+            return PamReturnCode::Ignore;
+        };
+
+        let authenticated = data::get(handle, Self::AUTHENTICATED_DATA);
+        let status = Self::account_status(
+            &config.get_user_entries(&user),
+            authenticated.as_deref(),
+            SystemTime::now(),
+        );
+
+        match status {
+            PamReturnCode::Acct_Expired => {
+                log.info("Apple Watch unlock has expired for user", &[]);
             }
+            PamReturnCode::Perm_Denied => {
+                log.info("Apple Watch unlock is disabled for user", &[]);
+            }
+            _ => {}
+        }
+
+        status
+    }
+
+    fn set_credentials(_: &PamHandle, _: Vec<&CStr>, _: c_uint) -> PamReturnCode {
+        // There are no credentials to establish, but success is returned
+        // rather than ignore as `pam_setcred` fails when every module it
+        // calls ignores it, which is the case after a `sufficient` unlock.
+        PamReturnCode::Success
+    }
+
+    fn open_session(handle: &PamHandle, args: Vec<&CStr>, _: c_uint) -> PamReturnCode {
+        let (args, mut log) = match Self::parse_args(handle, &args, "session") {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
         };
 
-        let Some(user) = config.get_user(&user.to_string()) else {
-            eprintln!("No config entry for '{user}'");
+        if !args.monitor {
             return PamReturnCode::Ignore;
+        }
+
+        let (user, config) = match Self::load_user(handle, &args, &mut log) {
+            Ok(loaded) => loaded,
+            Err(err) => return err,
         };
 
-        let conv = match ClientConv::try_from(handle) {
-            Ok(conv) => conv,
+        let now = SystemTime::now();
+        let entries: Vec<&Entry> = config
+            .get_user_entries(&user)
+            .into_iter()
+            .filter(|entry| entry.options.is_active(now))
+            .collect();
+
+        if entries.is_empty() {
+            log.debug("No Apple Watch to monitor for user", &[]);
+            return PamReturnCode::Ignore;
+        }
+
+        // Without the ID the monitor could only lock whichever session
+        // `loginctl` picks, which may not be the one being opened.
+        let Some(session_id) = data::env(handle, Self::SESSION_ID_ENV) else {
+            log.warning(
+                "No session ID to monitor, is pam_systemd.so before pam_apple_watch.so?",
+                &[],
+            );
+            return PamReturnCode::Ignore;
+        };
+
+        match SessionMonitor::for_session(&user).start(&args, &session_id, &entries) {
+            Ok(pid) => {
+                log.info(
+                    "Started monitoring Apple Watch presence",
+                    &[("monitor_pid", &pid)],
+                );
+                PamReturnCode::Success
+            }
             Err(err) => {
-                eprintln!("Unable to get pam_conv");
-                return err.0;
+                log.error(
+                    "Failed to start monitoring Apple Watch presence",
+                    &[("error", &err)],
+                );
+                PamReturnCode::Session_Err
             }
+        }
+    }
+
+    fn close_session(handle: &PamHandle, args: Vec<&CStr>, _: c_uint) -> PamReturnCode {
+        let (args, mut log) = match Self::parse_args(handle, &args, "session") {
+            Ok(parsed) => parsed,
+            Err(err) => return err,
         };
 
-        println!("Decoding Identity Resolution Key for Apple Watch");
-        let mut raw_irk: [u8; 16] = [0; 16];
-        match STANDARD.decode_slice(&user.encoded_irk, &mut raw_irk[..]) {
-            Err(err) => {
-                eprintln!("Failed to decode IRK: {err}");
-                return PamReturnCode::Authinfo_Unavail;
+        if !args.monitor {
+            return PamReturnCode::Ignore;
+        }
+
+        let user = match Self::user(handle, &mut log) {
+            Ok(user) => user,
+            Err(err) => return err,
+        };
+
+        match SessionMonitor::for_session(&user).stop() {
+            Ok(Some(pid)) => {
+                log.info(
+                    "Stopped monitoring Apple Watch presence",
+                    &[("monitor_pid", &pid)],
+                );
+                PamReturnCode::Success
             }
-            Ok(decoded_length) if decoded_length != 16 => {
-                eprintln!("Corrupt IRK, it must be 16 bytes long");
-                return PamReturnCode::Bad_Item;
+            Ok(None) => PamReturnCode::Ignore,
+            Err(err) => {
+                log.error(
+                    "Failed to stop monitoring Apple Watch presence",
+                    &[("error", &err)],
+                );
+                PamReturnCode::Session_Err
             }
-            Ok(_) => raw_irk.reverse(),
         }
-
-        async_runtime
-            .block_on(async { AppleWatchPAM::unlock_with_apple_watch(args, &conv, raw_irk).await })
     }
 }
 
 impl AppleWatchPAM {
     const DEFAULT_UNLOCK_THRESHOLD: i16 = -80;
+    const DEFAULT_RETRIES: u8 = 3;
+    const DEFAULT_RETRY_TIMEOUT_MS: u64 = 500;
 
-    async fn unlock_with_apple_watch(
-        args: HashMap<&str, &str>,
-        conv: &ClientConv<'_>,
-        irk: [u8; 16],
+    /// Specifies the PAM environment variable `pam_systemd` sets
+    /// to the ID of the session being opened.
+    const SESSION_ID_ENV: &CStr = c"XDG_SESSION_ID";
+
+    /// Specifies the PAM data holding the label of the watch that
+    /// authenticated the user, empty for a watch without a label.
+    const AUTHENTICATED_DATA: &CStr = c"pam_apple_watch_authenticated";
+
+    /// Records the entry of the watch that authenticated the user,
+    /// as account management only ever denies that watch.
+    fn set_authenticated(handle: &PamHandle, entry: &Entry, log: &Logger) {
+        let label = entry.options.label().unwrap_or_default();
+        if let Err(err) = data::set(handle, Self::AUTHENTICATED_DATA, &label) {
+            log.warning(
+                "Failed to record the unlocking Apple Watch",
+                &[("error", &err)],
+            );
+        }
+    }
+
+    /// Returns the result of account management for the user's entries,
+    /// given the label of the watch this module authenticated the user
+    /// with, if it did.
+    ///
+    /// Only that watch can deny the account, when it was disabled or
+    /// expired since authenticating, otherwise the account is ignored
+    /// so that a user who opted out of their watches with
+    /// `enabled=false` can still log in with a password.
+    fn account_status(
+        entries: &[&Entry],
+        authenticated: Option<&str>,
+        now: SystemTime,
     ) -> PamReturnCode {
-        let Ok(session) = bluer::Session::new().await else {
-            return PamReturnCode::Service_Err;
+        let Some(entry) = authenticated.and_then(|label| {
+            entries
+                .iter()
+                .find(|entry| entry.options.label().unwrap_or_default() == label)
+        }) else {
+            return PamReturnCode::Ignore;
         };
 
-        let Ok(adapter) = session.default_adapter().await else {
-            return PamReturnCode::Service_Err;
-        };
+        if entry.options.enabled() == Some(false) {
+            PamReturnCode::Perm_Denied
+        } else if entry.options.is_expired(now) {
+            PamReturnCode::Acct_Expired
+        } else {
+            PamReturnCode::Success
+        }
+    }
 
-        let unlock_threshold: i16 = args
-            .get("unlock_threshold")
-            .and_then(|value| value.parse().ok())
-            .unwrap_or(Self::DEFAULT_UNLOCK_THRESHOLD);
+    /// Parses the module arguments and creates the [`Logger`] they
+    /// configure, invalid arguments fail the PAM function with
+    /// `Service_Err` unless the `lenient` argument is given.
+    fn parse_args(
+        handle: &PamHandle,
+        args: &[&CStr],
+        function: &'static str,
+    ) -> Result<(ModuleArgs, Logger), PamReturnCode> {
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+        let (args, errors) = ModuleArgs::parse(args.iter().map(AsRef::as_ref));
+        let log = Logger::new(handle, &args, function);
+
+        for err in &errors {
+            if args.lenient {
+                log.warning("Ignoring invalid module argument", &[("error", err)]);
+            } else {
+                log.error("Invalid module argument", &[("error", err)]);
+            }
+        }
 
-        conv.info(c"Searching for Apple Watch");
-        let mut watch = AppleWatch::new(irk);
+        if !errors.is_empty() && !args.lenient {
+            return Err(PamReturnCode::Service_Err);
+        }
 
-        match watch
-            .find_watch(&adapter, 3, Duration::from_millis(500))
-            .await
-        {
+        Ok((args, log))
+    }
+
+    /// Returns the user the PAM function was called for, attaching
+    /// them to every message logged.
+    fn user(handle: &PamHandle, log: &mut Logger) -> Result<String, PamReturnCode> {
+        let user = match get_user(handle, None) {
+            Ok(user) => user.to_string(),
+            Err(err) => {
+                log.error("Failed to get current user", &[("error", &err)]);
+                return Err(err.0);
+            }
+        };
+
+        log.add_field("user", &user);
+        Ok(user)
+    }
+
+    /// Loads the module config, logging each entry that is skipped,
+    /// or returns `None` after logging why it couldn't be loaded.
+    fn load_config(args: &ModuleArgs, log: &Logger) -> Option<Config> {
+        let config = match Config::load(&args.config) {
+            Ok(config) => config,
             Err(err) => {
-                eprintln!("Failed to find Apple Watch: {err}");
-                conv.error(c"Apple Watch not available");
-                return PamReturnCode::Ignore;
+                log.error("Failed to get module config", &[("error", &err)]);
+                return None;
             }
-            Ok(tries) => println!("Found Apple Watch after {tries} tries"),
+        };
+
+        for skipped in &config.skipped {
+            log.warning("Skipping invalid config entry", &[("error", skipped)]);
         }
 
-        match watch.get_watch_status().await {
+        Some(config)
+    }
+
+    /// Returns the user the PAM function was called for along
+    /// with the module config.
+    fn load_user(
+        handle: &PamHandle,
+        args: &ModuleArgs,
+        log: &mut Logger,
+    ) -> Result<(String, Config), PamReturnCode> {
+        let user = Self::user(handle, log)?;
+        let config = Self::load_config(args, log).ok_or(PamReturnCode::No_Module_Data)?;
+        Ok((user, config))
+    }
+
+    /// Formats a message about the watch, naming the
+    /// watch by its label if it has one.
+    fn watch_message(label: Option<&str>, message: &str) -> CString {
+        let message = match label {
+            Some(label) => format!("Apple Watch '{label}' {message}"),
+            None => format!("Apple Watch {message}"),
+        };
+
+        CString::new(message).unwrap_or_default()
+    }
+
+    /// Selects the Bluetooth adapter for the watch and then checks
+    /// if it is able to unlock this device, returning the entry of
+    /// the watch if it is.
+    async fn unlock_with_entry<'a>(
+        session: &bluer::Session,
+        args: &ModuleArgs,
+        entry: &'a Entry,
+        decision: &RefCell<UnlockDecision>,
+        conv: &ClientConv<'_>,
+        log: &Logger,
+        irk: Irk,
+    ) -> Result<&'a Entry, PamReturnCode> {
+        let adapter = match entry.options.adapter().or_else(|| args.adapter.clone()) {
+            Some(name) => session.adapter(&name),
+            None => session.default_adapter().await,
+        };
+
+        let adapter = match adapter {
+            Ok(adapter) => adapter,
             Err(err) => {
-                eprintln!("Failed to get Apple Watch status: {err}");
-                conv.error(c"Apple Watch not available");
-                PamReturnCode::Ignore
+                log.error("Failed to select Bluetooth adapter", &[("error", &err)]);
+                return Err(PamReturnCode::Service_Err);
             }
-            Ok(status) => match status {
-                AppleWatchStatus { rssi, .. } if rssi < unlock_threshold => {
-                    eprintln!("Apple Watch RSSI: {rssi}, Target Threshold: {unlock_threshold}");
-                    conv.error(c"Apple Watch is too far away");
-                    PamReturnCode::Ignore
-                }
-                AppleWatchStatus { locked, .. } if locked => {
-                    conv.error(c"Apple Watch is locked");
-                    PamReturnCode::Ignore
-                }
-                AppleWatchStatus {
-                    device_auto_unlock_enabled,
-                    ..
-                } if !device_auto_unlock_enabled => {
-                    conv.error(c"Apple Watch is not configured to auto-unlock devices");
-                    PamReturnCode::Ignore
+        };
+
+        Self::unlock_with_apple_watch(&adapter, args, &entry.options, decision, conv, log, irk)
+            .await?;
+
+        Ok(entry)
+    }
+
+    /// Starts recording the decision for the watch, the per-user values
+    /// from the config entry are preferred over the module arguments.
+    fn new_decision(args: &ModuleArgs, user: &str, user_options: &EntryOptions) -> UnlockDecision {
+        let unlock_threshold: i16 = user_options
+            .unlock_threshold()
+            .or(args.unlock_threshold)
+            .unwrap_or(Self::DEFAULT_UNLOCK_THRESHOLD);
+
+        let require_auto_unlock: bool = user_options
+            .require_auto_unlock()
+            .or(args.require_auto_unlock)
+            .unwrap_or(true);
+
+        UnlockDecision::new(
+            user,
+            user_options.label().as_deref(),
+            args.rssi_filter,
+            unlock_threshold,
+            require_auto_unlock,
+        )
+    }
+
+    /// Returns the logger for messages about the watch, naming
+    /// the watch by its label if it has one.
+    fn watch_log(log: &Logger, label: Option<&str>) -> Logger {
+        let mut log = log.clone();
+        if let Some(label) = label {
+            log.add_field("label", label);
+        }
+
+        log
+    }
+
+    async fn unlock_with_apple_watch<S: Scanner>(
+        adapter: &S,
+        args: &ModuleArgs,
+        user_options: &EntryOptions,
+        decision: &RefCell<UnlockDecision>,
+        conv: &ClientConv<'_>,
+        log: &Logger,
+        irk: Irk,
+    ) -> Result<(), PamReturnCode> {
+        let log = Self::watch_log(log, user_options.label().as_deref());
+
+        // The per-user value from the config entry is
+        // preferred over the module argument.
+        let retries: u8 = user_options
+            .retries()
+            .or(args.retries)
+            .unwrap_or(Self::DEFAULT_RETRIES);
+
+        let retry_timeout = Duration::from_millis(
+            user_options
+                .retry_timeout()
+                .or(args.retry_timeout)
+                .unwrap_or(Self::DEFAULT_RETRY_TIMEOUT_MS),
+        );
+
+        let result = UnlockDecision::check(
+            decision,
+            adapter,
+            irk,
+            retries,
+            retry_timeout,
+            args.rssi_samples,
+            args.rssi_window,
+        )
+        .await;
+
+        let decision = decision.borrow();
+        if let Some(tries) = decision.tries {
+            log.debug("Found Apple Watch", &[("tries", &tries)]);
+        }
+
+        if let Err(err) = result {
+            match decision.denial {
+                Some(DenialReason::NotFound) => {
+                    log.info("Failed to find Apple Watch", &[("error", &err)]);
                 }
-                _ => {
-                    conv.info(c"Unlocking with Apple Watch");
-                    PamReturnCode::Success
+                Some(DenialReason::RssiUnavailable) => {
+                    log.warning("Failed to sample Apple Watch RSSI", &[("error", &err)]);
                 }
-            },
+                _ => log.warning("Failed to get Apple Watch status", &[("error", &err)]),
+            }
+        }
+
+        Self::report_decision(&decision, args, conv, &log)
+    }
+
+    /// Records the decision and tells the user why the watch
+    /// can't unlock this device if it was denied.
+    fn report_decision(
+        decision: &UnlockDecision,
+        args: &ModuleArgs,
+        conv: &ClientConv<'_>,
+        log: &Logger,
+    ) -> Result<(), PamReturnCode> {
+        Self::record_decision(decision, args, log);
+
+        match decision.denial {
+            Some(reason) => {
+                conv.error(&Self::watch_message(
+                    decision.label.as_deref(),
+                    reason.message(),
+                ));
+                Err(PamReturnCode::Ignore)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records the decision in the log, and in the decision
+    /// log if one is configured.
+    fn record_decision(decision: &UnlockDecision, args: &ModuleArgs, log: &Logger) {
+        let outcome = if decision.unlocked { "unlock" } else { "deny" };
+        let mut fields: Vec<(&str, &dyn Display)> = vec![
+            ("decision", &outcome),
+            ("threshold", &decision.unlock_threshold),
+        ];
+        if let Some(rssi) = &decision.rssi {
+            fields.push(("rssi", rssi));
+        }
+        if let Some(tries) = &decision.tries {
+            fields.push(("tries", tries));
         }
+
+        match decision.denial {
+            Some(reason) => log.info(&format!("Apple Watch {}", reason.message()), &fields),
+            None => log.info("Apple Watch can unlock", &fields),
+        }
+
+        if let Some(decision_log) = &args.decision_log
+            && let Err(err) = decision.append_to(decision_log)
+        {
+            log.warning(
+                "Failed to write decision log",
+                &[("path", &decision_log.display()), ("error", &err)],
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppleWatchPAM;
+    use crate::lib::conf::Entry;
+
+    use pam::PamReturnCode;
+    use std::time::SystemTime;
+
+    const IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+
+    /// Returns the entry written on the config line.
+    fn entry(options: &str) -> Entry {
+        Entry::try_from((0, &format!("alice;{IRK}{options}"))).expect("valid entry")
+    }
+
+    #[test]
+    fn opted_out_user_passes_account_management() {
+        let disabled = entry(";enabled=false");
+        let expired = entry(";label=spare;expires=2024-01-01");
+
+        assert_eq!(
+            AppleWatchPAM::account_status(&[&disabled, &expired], None, SystemTime::now()),
+            PamReturnCode::Ignore
+        );
+        assert_eq!(
+            AppleWatchPAM::account_status(&[], None, SystemTime::now()),
+            PamReturnCode::Ignore
+        );
+    }
+
+    #[test]
+    fn only_the_authenticating_watch_is_checked() {
+        let active = entry("");
+        let disabled = entry(";label=spare;enabled=false");
+        let entries = [&active, &disabled];
+
+        assert_eq!(
+            AppleWatchPAM::account_status(&entries, Some(""), SystemTime::now()),
+            PamReturnCode::Success
+        );
+
+        // A watch that has since been removed is no longer checked
+        assert_eq!(
+            AppleWatchPAM::account_status(&entries, Some("sport"), SystemTime::now()),
+            PamReturnCode::Ignore
+        );
+    }
+
+    #[test]
+    fn authenticating_watch_disabled_since_is_denied() {
+        let disabled = entry(";label=sport;enabled=false");
+        let expired = entry(";label=spare;expires=2024-01-01");
+        let entries = [&disabled, &expired];
+
+        assert_eq!(
+            AppleWatchPAM::account_status(&entries, Some("sport"), SystemTime::now()),
+            PamReturnCode::Perm_Denied
+        );
+        assert_eq!(
+            AppleWatchPAM::account_status(&entries, Some("spare"), SystemTime::now()),
+            PamReturnCode::Acct_Expired
+        );
     }
 }
@@ -0,0 +1,256 @@
+use crate::lib::args::{LogBackend, ModuleArgs};
+
+use pam::{PamHandle, PamItemType, get_item};
+use std::ffi::{CStr, CString};
+use std::fmt::{Display, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+/// Specifies the identifier the module logs under.
+const IDENTIFIER: &str = "pam_apple_watch";
+
+/// Specifies the socket journald receives native protocol messages on.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Specifies the syslog facility number of `LOG_AUTHPRIV`, which is
+/// what journald expects in the `SYSLOG_FACILITY` field.
+const AUTHPRIV_FACILITY: i32 = libc::LOG_AUTHPRIV >> 3;
+
+/// Specifies the severity of a log message, ordered from
+/// the most to the least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl Level {
+    /// Returns the syslog priority of the level.
+    fn priority(self) -> i32 {
+        match self {
+            Self::Error => libc::LOG_ERR,
+            Self::Warning => libc::LOG_WARNING,
+            Self::Info => libc::LOG_INFO,
+            Self::Debug => libc::LOG_DEBUG,
+        }
+    }
+}
+
+/// Logs the messages of the PAM module, as the standard output and
+/// error of the application calling the module, e.g. gdm, sddm or
+/// kscreenlocker, is rarely seen.
+///
+/// The verbosity is controlled with the `debug` and `quiet` module
+/// arguments, and the backend with `log=syslog` or `log=journald`,
+/// which defaults to journald when it is running.
+#[derive(Clone)]
+pub struct Logger {
+    backend: LogBackend,
+    max_level: Level,
+
+    /// Specifies the PAM function, e.g. `auth`, being logged for.
+    function: &'static str,
+
+    /// Specifies the fields attached to every message.
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Logger {
+    /// Creates a [`Logger`] for the PAM function, configured by the
+    /// module arguments.
+    pub fn new(handle: &PamHandle, args: &ModuleArgs, function: &'static str) -> Self {
+        let max_level = if args.debug {
+            Level::Debug
+        } else if args.quiet {
+            Level::Warning
+        } else {
+            Level::Info
+        };
+
+        let backend = args.log.unwrap_or_else(|| {
+            if Path::new(JOURNALD_SOCKET).exists() {
+                LogBackend::Journald
+            } else {
+                LogBackend::Syslog
+            }
+        });
+
+        let mut fields = Vec::new();
+        if let Some(service) = Self::service(handle) {
+            fields.push(("service", service));
+        }
+
+        Self {
+            backend,
+            max_level,
+            function,
+            fields,
+        }
+    }
+
+    /// Attaches a field, e.g. the user being authenticated,
+    /// to every message.
+    pub fn add_field(&mut self, key: &'static str, value: &str) {
+        self.fields.push((key, value.to_string()));
+    }
+
+    pub fn error(&self, message: &str, fields: &[(&str, &dyn Display)]) {
+        self.log(Level::Error, message, fields);
+    }
+
+    pub fn warning(&self, message: &str, fields: &[(&str, &dyn Display)]) {
+        self.log(Level::Warning, message, fields);
+    }
+
+    pub fn info(&self, message: &str, fields: &[(&str, &dyn Display)]) {
+        self.log(Level::Info, message, fields);
+    }
+
+    pub fn debug(&self, message: &str, fields: &[(&str, &dyn Display)]) {
+        self.log(Level::Debug, message, fields);
+    }
+
+    /// Writes the message, along with its structured fields, if the
+    /// level is enabled, falling back to syslog if journald can't
+    /// be reached.
+    pub fn log(&self, level: Level, message: &str, fields: &[(&str, &dyn Display)]) {
+        if level > self.max_level {
+            return;
+        }
+
+        let fields: Vec<(&str, String)> = self
+            .fields
+            .iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .chain(fields.iter().map(|(key, value)| (*key, value.to_string())))
+            .collect();
+
+        if self.backend == LogBackend::Journald && self.journald(level, message, &fields).is_ok() {
+            return;
+        }
+
+        self.syslog(level, message, &fields);
+    }
+
+    /// Writes the message to syslog in the same format as `pam_syslog`,
+    /// e.g. `pam_apple_watch(kde:auth): message (user=admin)`.
+    fn syslog(&self, level: Level, message: &str, fields: &[(&str, String)]) {
+        let service = fields
+            .iter()
+            .find(|(key, _)| *key == "service")
+            .map_or("?", |(_, service)| service.as_str());
+
+        let mut line = format!("{IDENTIFIER}({service}:{}): {message}", self.function);
+        let fields: Vec<String> = fields
+            .iter()
+            .filter(|(key, _)| *key != "service")
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+
+        if !fields.is_empty() {
+            let _ = write!(line, " ({})", fields.join(", "));
+        }
+
+        let line = CString::new(line.replace('\0', "")).unwrap_or_default();
+
+        // SAFETY: the message is passed as an argument, rather than as
+        // the format string, so it can't be interpreted by syslog.
+        unsafe {
+            libc::syslog(
+                libc::LOG_AUTHPRIV | level.priority(),
+                c"%s".as_ptr(),
+                line.as_ptr(),
+            );
+        }
+    }
+
+    /// Writes the message to journald using its native protocol, each
+    /// structured field becomes a journal field of the same name in
+    /// upper case, e.g. `RSSI=-62`.
+    fn journald(
+        &self,
+        level: Level,
+        message: &str,
+        fields: &[(&str, String)],
+    ) -> std::io::Result<()> {
+        let mut datagram = Vec::new();
+        Self::journal_field(&mut datagram, "MESSAGE", message);
+        Self::journal_field(&mut datagram, "PRIORITY", &level.priority().to_string());
+        Self::journal_field(
+            &mut datagram,
+            "SYSLOG_FACILITY",
+            &AUTHPRIV_FACILITY.to_string(),
+        );
+        Self::journal_field(&mut datagram, "SYSLOG_IDENTIFIER", IDENTIFIER);
+        Self::journal_field(&mut datagram, "PAM_FUNCTION", self.function);
+
+        for (key, value) in fields {
+            Self::journal_field(&mut datagram, &key.to_ascii_uppercase(), value);
+        }
+
+        UnixDatagram::unbound()?.send_to(&datagram, JOURNALD_SOCKET)?;
+        Ok(())
+    }
+
+    /// Appends a field to a journald native protocol datagram, values
+    /// containing a newline are written with an explicit length.
+    fn journal_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+        datagram.extend_from_slice(name.as_bytes());
+
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+
+    /// Returns the name of the PAM service, e.g. `kde`, the
+    /// module was called for.
+    fn service(handle: &PamHandle) -> Option<String> {
+        let service: *const libc::c_void = get_item(handle, PamItemType::Service).ok()?;
+
+        // SAFETY: PAM stores the service as a NUL terminated string,
+        // it is copied before the handle is used again.
+        let service = unsafe { CStr::from_ptr(service.cast()) };
+        Some(service.to_string_lossy().into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Logger;
+
+    #[test]
+    fn journal_field_is_written_as_name_and_value() {
+        let mut datagram = Vec::new();
+        Logger::journal_field(&mut datagram, "RSSI", "-62");
+        Logger::journal_field(&mut datagram, "THRESHOLD", "-80");
+
+        assert_eq!(datagram, b"RSSI=-62\nTHRESHOLD=-80\n");
+    }
+
+    #[test]
+    fn journal_field_with_a_newline_is_written_with_its_length() {
+        let mut datagram = Vec::new();
+        Logger::journal_field(&mut datagram, "MESSAGE", "first\nsecond");
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&12u64.to_le_bytes());
+        expected.extend_from_slice(b"first\nsecond\n");
+        assert_eq!(datagram, expected);
+    }
+
+    #[test]
+    fn empty_journal_field_keeps_its_name() {
+        let mut datagram = Vec::new();
+        Logger::journal_field(&mut datagram, "LABEL", "");
+
+        assert_eq!(datagram, b"LABEL=\n");
+    }
+}
@@ -0,0 +1,404 @@
+use crate::lib::args::ModuleArgs;
+use crate::lib::conf::Entry;
+use crate::lib::nss::{self, Credentials};
+
+use std::fs::DirBuilder;
+use std::io::{ErrorKind, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// Specifies the CLI tool that monitors the presence of the
+/// Apple Watch, as installed by `make install`.
+const MONITOR_COMMAND: &str = "/usr/bin/watch_unlock_cli";
+
+/// Specifies the sub-command of the CLI tool that monitors the
+/// presence of the Apple Watch.
+const MONITOR_SUBCOMMAND: &str = "monitor";
+
+/// Specifies the directory holding the PID of each session's monitor.
+const MONITOR_RUN_DIR: &str = "/run/pam_apple_watch";
+
+/// Specifies the permissions of [`MONITOR_RUN_DIR`], only root may
+/// create or remove the PID files that decide which process is killed.
+const MONITOR_RUN_DIR_MODE: u32 = 0o700;
+
+/// The process monitoring the presence of the user's Apple Watch
+/// for the length of a session, which locks the session when the
+/// watch leaves.
+///
+/// The monitor is identified by the user and the process that opened
+/// the session, PAM requires that the same process closes the session.
+pub struct SessionMonitor {
+    user: String,
+    pid_file: PathBuf,
+}
+
+impl SessionMonitor {
+    /// Returns the monitor of the user's session opened by this process.
+    pub fn for_session(user: &str) -> Self {
+        let pid_file =
+            Path::new(MONITOR_RUN_DIR).join(format!("{user}-{}.pid", std::process::id()));
+
+        Self {
+            user: user.to_string(),
+            pid_file,
+        }
+    }
+
+    /// Starts the monitor, as a process of the user detached from the
+    /// session, returning its PID. The monitor uses the same adapter,
+    /// search and checks as the module arguments, and locks only the
+    /// session with the ID.
+    ///
+    /// Only root can read the config, so the user's entries are written
+    /// to the monitor's standard input instead.
+    pub fn start(
+        &self,
+        args: &ModuleArgs,
+        session_id: &str,
+        entries: &[&Entry],
+    ) -> std::io::Result<u32> {
+        let Credentials { uid, gid, groups } = nss::credentials(&self.user)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "unknown user"))?;
+
+        DirBuilder::new()
+            .recursive(true)
+            .mode(MONITOR_RUN_DIR_MODE)
+            .create(MONITOR_RUN_DIR)?;
+
+        let mut command = self.command(args, session_id);
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0);
+
+        // SAFETY: the closure only makes system calls, which are safe
+        // to make between `fork` and `exec`, the groups are looked up
+        // beforehand as NSS isn't.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setgroups(groups.len(), groups.as_ptr()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+        }
+
+        let mut monitor = command.spawn()?;
+        let pid = monitor.id();
+
+        let written = Self::write_entries(&mut monitor, entries).and_then(|()| {
+            // The start time tells the monitor apart from a later process
+            // that is given the same PID once the monitor has exited.
+            match libc::pid_t::try_from(pid).ok().and_then(Self::start_time) {
+                Some(start_time) => std::fs::write(&self.pid_file, format!("{pid} {start_time}")),
+                None => Err(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    "monitor exited at once",
+                )),
+            }
+        });
+
+        if let Err(err) = written {
+            // A monitor that can't be stopped when the session closes
+            // mustn't be left running.
+            let _ = monitor.kill();
+            let _ = monitor.wait();
+            let _ = std::fs::remove_file(&self.pid_file);
+            return Err(err);
+        }
+
+        Ok(pid)
+    }
+
+    /// Returns the command that runs the monitor for the session.
+    fn command(&self, args: &ModuleArgs, session_id: &str) -> Command {
+        let mut command = Command::new(MONITOR_COMMAND);
+        command
+            .arg(MONITOR_SUBCOMMAND)
+            .arg(&self.user)
+            .arg("--entries-from-stdin")
+            .arg("--session")
+            .arg(session_id);
+
+        if let Some(adapter) = &args.adapter {
+            command.arg("--adapter").arg(adapter);
+        }
+
+        if let Some(require_auto_unlock) = args.require_auto_unlock {
+            command
+                .arg("--require-auto-unlock")
+                .arg(require_auto_unlock.to_string());
+        }
+
+        if let Some(unlock_threshold) = args.unlock_threshold {
+            command
+                .arg("--unlock-threshold")
+                .arg(unlock_threshold.to_string());
+        }
+
+        // The monitor searches for the watch with the same timing as
+        // authentication, so a watch that unlocks is also present.
+        if let Some(retries) = args.retries {
+            command.arg("--retries").arg(retries.to_string());
+        }
+
+        if let Some(retry_timeout) = args.retry_timeout {
+            command
+                .arg("--retry-timeout")
+                .arg(retry_timeout.to_string());
+        }
+
+        if let Some(max_wait) = args.max_wait {
+            command
+                .arg("--max-wait")
+                .arg(max_wait.as_millis().to_string());
+        }
+
+        command
+    }
+
+    /// Writes the entries, one per line as in the config, to the
+    /// standard input of the monitor and then closes it.
+    fn write_entries(monitor: &mut Child, entries: &[&Entry]) -> std::io::Result<()> {
+        let Some(mut stdin) = monitor.stdin.take() else {
+            return Err(std::io::Error::new(
+                ErrorKind::BrokenPipe,
+                "monitor has no standard input",
+            ));
+        };
+
+        for entry in entries {
+            writeln!(stdin, "{entry}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops the monitor, returning its PID, or `None` if no monitor
+    /// was started for the session or it has already exited.
+    ///
+    /// The PID file is removed however the monitor is stopped, so a
+    /// stale file is never left for a later session with the same PID.
+    pub fn stop(&self) -> std::io::Result<Option<u32>> {
+        let pid_file = match std::fs::read_to_string(&self.pid_file) {
+            Ok(pid_file) => pid_file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                let _ = std::fs::remove_file(&self.pid_file);
+                return Err(err);
+            }
+        };
+
+        let stopped = self.kill(&pid_file);
+        match std::fs::remove_file(&self.pid_file) {
+            Ok(()) => stopped,
+            Err(err) if err.kind() == ErrorKind::NotFound => stopped,
+            Err(err) => stopped.and(Err(err)),
+        }
+    }
+
+    /// Terminates the monitor described by the PID file, if it is
+    /// still running.
+    fn kill(&self, pid_file: &str) -> std::io::Result<Option<u32>> {
+        let invalid = || std::io::Error::new(ErrorKind::InvalidData, "invalid monitor PID file");
+
+        let mut fields = pid_file.split_whitespace();
+        let pid: libc::pid_t = fields
+            .next()
+            .and_then(|pid| pid.parse().ok())
+            .ok_or_else(invalid)?;
+        let start_time: u64 = fields
+            .next()
+            .and_then(|start_time| start_time.parse().ok())
+            .ok_or_else(invalid)?;
+
+        // The pidfd keeps referring to the process it was opened for,
+        // so once the process is checked to be the monitor the signal
+        // can't reach another process that has since reused the PID.
+        let Some(pidfd) = Self::pidfd_open(pid)? else {
+            return Ok(None);
+        };
+
+        if !self.is_monitor(pid, start_time) {
+            return Ok(None);
+        }
+
+        // SAFETY: the pidfd is valid for the length of the call and the
+        // remaining arguments are those documented for no `siginfo`.
+        let sent = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                pidfd.as_raw_fd(),
+                libc::SIGTERM,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+
+        if sent != 0 {
+            let err = std::io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ESRCH) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        // SAFETY: waiting reaps the monitor if this process started
+        // it and otherwise returns at once.
+        unsafe {
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+        }
+
+        Ok(u32::try_from(pid).ok())
+    }
+
+    /// Opens a pidfd for the process, or returns `None` if it
+    /// isn't running.
+    fn pidfd_open(pid: libc::pid_t) -> std::io::Result<Option<OwnedFd>> {
+        // SAFETY: `pidfd_open` takes a PID and flags, and returns a new
+        // file descriptor owned by the caller or -1.
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            let err = std::io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ESRCH) => Ok(None),
+                _ => Err(err),
+            };
+        }
+
+        let fd = i32::try_from(fd)
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "invalid pidfd"))?;
+
+        // SAFETY: the file descriptor was just returned by the kernel
+        // and nothing else owns it.
+        Ok(Some(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Returns the time, in clock ticks since boot, the process
+    /// started, or `None` if it isn't running.
+    fn start_time(pid: libc::pid_t) -> Option<u64> {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+
+        // The command name may itself contain spaces and parentheses,
+        // the fields after it start with the state, the third field.
+        let (_, fields) = stat.rsplit_once(')')?;
+        fields.split_whitespace().nth(19)?.parse().ok()
+    }
+
+    /// Returns true if the process is the monitor that was started,
+    /// running the monitor sub-command for this session's user.
+    fn is_monitor(&self, pid: libc::pid_t, start_time: u64) -> bool {
+        if Self::start_time(pid) != Some(start_time) {
+            return false;
+        }
+
+        let Ok(cmdline) = std::fs::read(format!("/proc/{pid}/cmdline")) else {
+            return false;
+        };
+
+        let mut args = cmdline.split(|&c| c == 0);
+        args.next() == Some(MONITOR_COMMAND.as_bytes())
+            && args
+                .skip_while(|&arg| arg != MONITOR_SUBCOMMAND.as_bytes())
+                .nth(1)
+                == Some(self.user.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionMonitor;
+    use crate::lib::args::ModuleArgs;
+
+    use std::process::Command;
+
+    /// Returns a monitor whose PID file is in the temporary directory.
+    fn monitor(name: &str) -> SessionMonitor {
+        SessionMonitor {
+            user: "test".to_string(),
+            pid_file: std::env::temp_dir()
+                .join(format!("pam_apple_watch-{name}-{}.pid", std::process::id())),
+        }
+    }
+
+    /// Returns the arguments the monitor is started with.
+    fn monitor_args(args: &[&str]) -> Vec<String> {
+        let (args, errors) = ModuleArgs::parse(args.iter().copied());
+        assert!(errors.is_empty(), "{errors:?}");
+
+        monitor("args")
+            .command(&args, "c2")
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn monitor_locks_only_its_session() {
+        assert_eq!(
+            monitor_args(&[]),
+            ["monitor", "test", "--entries-from-stdin", "--session", "c2"]
+        );
+    }
+
+    #[test]
+    fn monitor_uses_the_module_arguments() {
+        assert_eq!(
+            monitor_args(&["adapter=hci1", "unlock_threshold=-70"])[5..],
+            ["--adapter", "hci1", "--unlock-threshold", "-70"]
+        );
+    }
+
+    #[test]
+    fn monitor_searches_with_the_module_timing() {
+        assert_eq!(
+            monitor_args(&["retries=5", "retry_timeout=750", "max_wait=2000"])[5..],
+            [
+                "--retries",
+                "5",
+                "--retry-timeout",
+                "750",
+                "--max-wait",
+                "2000"
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_without_pid_file_stops_nothing() {
+        assert_eq!(monitor("missing").stop().unwrap(), None);
+    }
+
+    #[test]
+    fn stop_leaves_other_processes_running() {
+        let mut sleep = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = libc::pid_t::try_from(sleep.id()).unwrap();
+        let start_time = SessionMonitor::start_time(pid).unwrap();
+
+        let monitor = monitor("other");
+        std::fs::write(&monitor.pid_file, format!("{pid} {start_time}")).unwrap();
+        assert_eq!(monitor.stop().unwrap(), None);
+        assert!(!monitor.pid_file.exists());
+        assert_eq!(sleep.try_wait().unwrap(), None);
+
+        sleep.kill().unwrap();
+        sleep.wait().unwrap();
+    }
+
+    #[test]
+    fn stop_removes_an_invalid_pid_file() {
+        let monitor = monitor("invalid");
+        std::fs::write(&monitor.pid_file, "not a pid").unwrap();
+        assert!(monitor.stop().is_err());
+        assert!(!monitor.pid_file.exists());
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Specifies how multiple received signal strength indicator samples
+/// are combined into the single value compared against the unlock
+/// threshold, smoothing out the large swings of individual samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RssiFilter {
+    /// Uses the middle value of the sorted samples.
+    #[default]
+    Median,
+
+    /// Uses the mean of the samples after discarding the
+    /// highest and lowest [`RssiFilter::TRIM_PERCENT`] of them.
+    TrimmedMean,
+
+    /// Uses an exponential moving average of the samples, in
+    /// the order they were received, weighted by [`RssiFilter::EMA_ALPHA`].
+    ExponentialMovingAverage,
+
+    /// Uses a one-dimensional Kalman filter over the samples,
+    /// in the order they were received.
+    Kalman,
+}
+
+impl RssiFilter {
+    /// Specifies the percentage of samples discarded from each end
+    /// of the sorted samples by [`RssiFilter::TrimmedMean`].
+    const TRIM_PERCENT: usize = 20;
+
+    /// Specifies the weight given to each new sample by
+    /// [`RssiFilter::ExponentialMovingAverage`].
+    const EMA_ALPHA: f64 = 0.3;
+
+    /// Specifies the process noise, in dBm², assumed by
+    /// [`RssiFilter::Kalman`] between samples.
+    const KALMAN_PROCESS_NOISE: f64 = 0.5;
+
+    /// Specifies the measurement noise, in dBm², assumed by
+    /// [`RssiFilter::Kalman`] for each sample.
+    const KALMAN_MEASUREMENT_NOISE: f64 = 4.0;
+
+    /// Applies the filter to the supplied samples, which must be
+    /// in the order they were received, returning `None` if there
+    /// are no samples.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    pub fn apply(self, samples: &[i16]) -> Option<i16> {
+        let (first, _) = samples.split_first()?;
+
+        let filtered = match self {
+            Self::Median => {
+                let mut sorted = samples.to_vec();
+                sorted.sort_unstable();
+
+                let middle = sorted.len() / 2;
+                if sorted.len().is_multiple_of(2) {
+                    f64::midpoint(f64::from(sorted[middle - 1]), f64::from(sorted[middle]))
+                } else {
+                    f64::from(sorted[middle])
+                }
+            }
+
+            Self::TrimmedMean => {
+                let mut sorted = samples.to_vec();
+                sorted.sort_unstable();
+
+                let trim = sorted.len() * Self::TRIM_PERCENT / 100;
+                let kept = &sorted[trim..sorted.len() - trim];
+
+                kept.iter().copied().map(f64::from).sum::<f64>() / kept.len() as f64
+            }
+
+            Self::ExponentialMovingAverage => {
+                samples
+                    .iter()
+                    .skip(1)
+                    .fold(f64::from(*first), |average, sample| {
+                        Self::EMA_ALPHA * f64::from(*sample) + (1.0 - Self::EMA_ALPHA) * average
+                    })
+            }
+
+            Self::Kalman => {
+                let mut estimate = f64::from(*first);
+                let mut error = Self::KALMAN_MEASUREMENT_NOISE;
+
+                for sample in samples.iter().skip(1) {
+                    error += Self::KALMAN_PROCESS_NOISE;
+
+                    let gain = error / (error + Self::KALMAN_MEASUREMENT_NOISE);
+                    estimate += gain * (f64::from(*sample) - estimate);
+                    error *= 1.0 - gain;
+                }
+
+                estimate
+            }
+        };
+
+        // The filtered value is always within the range of the
+        // samples so it can't overflow an i16.
+        Some(filtered.round() as i16)
+    }
+}
+
+impl FromStr for RssiFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median" => Ok(Self::Median),
+            "trimmed_mean" => Ok(Self::TrimmedMean),
+            "ema" => Ok(Self::ExponentialMovingAverage),
+            "kalman" => Ok(Self::Kalman),
+            other => Err(format!(
+                "unknown RSSI filter '{other}', expected one of: median, trimmed_mean, ema, kalman"
+            )),
+        }
+    }
+}
+
+impl Display for RssiFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Median => write!(f, "median"),
+            Self::TrimmedMean => write!(f, "trimmed_mean"),
+            Self::ExponentialMovingAverage => write!(f, "ema"),
+            Self::Kalman => write!(f, "kalman"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RssiFilter;
+
+    const FILTERS: [RssiFilter; 4] = [
+        RssiFilter::Median,
+        RssiFilter::TrimmedMean,
+        RssiFilter::ExponentialMovingAverage,
+        RssiFilter::Kalman,
+    ];
+
+    /// Specifies samples with a single outlier, as seen when the
+    /// watch is briefly shielded by the user's body.
+    const SAMPLES: [i16; 5] = [-60, -70, -65, -90, -62];
+
+    #[test]
+    fn no_samples_have_no_value() {
+        for filter in FILTERS {
+            assert_eq!(filter.apply(&[]), None, "{filter}");
+        }
+    }
+
+    #[test]
+    fn single_sample_is_its_own_value() {
+        for filter in FILTERS {
+            assert_eq!(filter.apply(&[-72]), Some(-72), "{filter}");
+        }
+    }
+
+    #[test]
+    fn median_uses_the_middle_samples() {
+        assert_eq!(RssiFilter::Median.apply(&SAMPLES), Some(-65));
+        assert_eq!(RssiFilter::Median.apply(&[-60, -70]), Some(-65));
+        assert_eq!(RssiFilter::Median.apply(&[-60, -61]), Some(-61));
+    }
+
+    #[test]
+    fn trimmed_mean_discards_the_outliers() {
+        assert_eq!(RssiFilter::TrimmedMean.apply(&SAMPLES), Some(-66));
+
+        // Too few samples to trim any of them
+        assert_eq!(RssiFilter::TrimmedMean.apply(&[-60, -90]), Some(-75));
+    }
+
+    #[test]
+    fn moving_averages_follow_the_sample_order() {
+        assert_eq!(
+            RssiFilter::ExponentialMovingAverage.apply(&[-60, -70]),
+            Some(-63)
+        );
+        assert_eq!(
+            RssiFilter::ExponentialMovingAverage.apply(&[-70, -60]),
+            Some(-67)
+        );
+        assert_eq!(RssiFilter::Kalman.apply(&[-60, -80]), Some(-71));
+        assert_eq!(RssiFilter::Kalman.apply(&[-60, -60, -60]), Some(-60));
+    }
+
+    #[test]
+    fn filtered_value_is_within_the_samples() {
+        for filter in FILTERS {
+            let rssi = filter.apply(&SAMPLES).unwrap();
+            assert!((-90..=-60).contains(&rssi), "{filter}: {rssi}");
+        }
+
+        for filter in FILTERS {
+            assert_eq!(
+                filter.apply(&[i16::MIN, i16::MIN]),
+                Some(i16::MIN),
+                "{filter}"
+            );
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for filter in FILTERS {
+            assert_eq!(filter.to_string().parse::<RssiFilter>(), Ok(filter));
+        }
+
+        assert!("mean".parse::<RssiFilter>().is_err());
+    }
+}
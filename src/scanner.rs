@@ -0,0 +1,113 @@
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+use async_trait::async_trait;
+use bluer::{Adapter, AdapterEvent, Address, Device, DeviceEvent, DiscoveryFilter};
+use futures::StreamExt;
+use futures::stream::LocalBoxStream;
+use std::collections::HashMap;
+
+/// Abstracts the parts of a Bluetooth adapter that are required
+/// to search for an Apple Watch, allowing for the search to be
+/// performed against something other than a real radio.
+///
+/// The method names intentionally mirror those of [`bluer::Adapter`]
+/// which is the backend used in production.
+#[async_trait(?Send)]
+pub trait Scanner {
+    type Device: ScannedDevice;
+
+    /// Sets the power state of the Bluetooth adapter.
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()>;
+
+    /// Configures the filter applied to subsequent device discovery.
+    async fn set_discovery_filter(&self, filter: DiscoveryFilter) -> bluer::Result<()>;
+
+    /// Starts device discovery, returning a stream of adapter events
+    /// that ends the discovery session when it is dropped.
+    async fn discover_devices(&self) -> bluer::Result<LocalBoxStream<'static, AdapterEvent>>;
+
+    /// Returns the addresses of the devices already known to the
+    /// adapter, such as those seen during a recent discovery.
+    async fn device_addresses(&self) -> bluer::Result<Vec<Address>>;
+
+    /// Returns the device with the specified address, the device
+    /// does not need to have been discovered.
+    fn device(&self, address: Address) -> bluer::Result<Self::Device>;
+}
+
+/// Abstracts the properties of a discovered Bluetooth device that
+/// are used to identify, and determine the status of, an Apple Watch.
+#[async_trait(?Send)]
+pub trait ScannedDevice: Clone + 'static {
+    /// Returns the Bluetooth address of the device.
+    #[cfg(feature = "cli")]
+    fn address(&self) -> Address;
+
+    /// Returns the received signal strength indicator of the
+    /// device, if one is available.
+    async fn rssi(&self) -> bluer::Result<Option<i16>>;
+
+    /// Returns the transmission power advertised by the device,
+    /// if it advertised one.
+    #[cfg(feature = "cli")]
+    async fn tx_power(&self) -> bluer::Result<Option<i16>>;
+
+    /// Returns the manufacturer specific advertisement data of
+    /// the device keyed by the manufacturer company identifier.
+    async fn manufacturer_data(&self) -> bluer::Result<Option<HashMap<u16, Vec<u8>>>>;
+
+    /// Returns a stream of changes to the properties of the device,
+    /// such as a new RSSI value or manufacturer data.
+    async fn events(&self) -> bluer::Result<LocalBoxStream<'static, DeviceEvent>>;
+}
+
+#[async_trait(?Send)]
+impl Scanner for Adapter {
+    type Device = Device;
+
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+        Adapter::set_powered(self, powered).await
+    }
+
+    async fn set_discovery_filter(&self, filter: DiscoveryFilter) -> bluer::Result<()> {
+        Adapter::set_discovery_filter(self, filter).await
+    }
+
+    async fn discover_devices(&self) -> bluer::Result<LocalBoxStream<'static, AdapterEvent>> {
+        Ok(Adapter::discover_devices(self).await?.boxed_local())
+    }
+
+    async fn device_addresses(&self) -> bluer::Result<Vec<Address>> {
+        Adapter::device_addresses(self).await
+    }
+
+    fn device(&self, address: Address) -> bluer::Result<Device> {
+        Adapter::device(self, address)
+    }
+}
+
+#[async_trait(?Send)]
+impl ScannedDevice for Device {
+    #[cfg(feature = "cli")]
+    fn address(&self) -> Address {
+        Device::address(self)
+    }
+
+    async fn rssi(&self) -> bluer::Result<Option<i16>> {
+        Device::rssi(self).await
+    }
+
+    #[cfg(feature = "cli")]
+    async fn tx_power(&self) -> bluer::Result<Option<i16>> {
+        Device::tx_power(self).await
+    }
+
+    async fn manufacturer_data(&self) -> bluer::Result<Option<HashMap<u16, Vec<u8>>>> {
+        Device::manufacturer_data(self).await
+    }
+
+    async fn events(&self) -> bluer::Result<LocalBoxStream<'static, DeviceEvent>> {
+        Ok(Device::events(self).await?.boxed_local())
+    }
+}
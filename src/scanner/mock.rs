@@ -0,0 +1,252 @@
+use crate::lib::scanner::{ScannedDevice, Scanner};
+
+use async_trait::async_trait;
+use bluer::{AdapterEvent, Address, DeviceEvent, DeviceProperty, DiscoveryFilter, ErrorKind};
+use futures::StreamExt;
+use futures::channel::mpsc::UnboundedSender;
+use futures::stream::LocalBoxStream;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// A synthetic Bluetooth Low Energy advertisement that will be
+/// emitted by a [`MockScanner`] during device discovery.
+#[derive(Debug, Clone)]
+pub struct MockAdvertisement {
+    /// Specifies how long to wait, after the previous advertisement,
+    /// before this advertisement is emitted.
+    pub delay: Duration,
+
+    /// Specifies the Bluetooth address of the advertising device.
+    pub address: Address,
+
+    /// Specifies the received signal strength indicator of the
+    /// advertisement, if any.
+    pub rssi: Option<i16>,
+
+    /// Specifies the transmission power advertised by the
+    /// device, if any.
+    pub tx_power: Option<i16>,
+
+    /// Specifies the manufacturer specific data included in the
+    /// advertisement keyed by company identifier.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+}
+
+/// An in-memory [`Scanner`] that emits a scripted sequence of
+/// [`MockAdvertisement`]s instead of using a real Bluetooth radio.
+///
+/// The script is consumed in order across discovery sessions, so an
+/// advertisement that was emitted during one discovery attempt will
+/// not be emitted again during a subsequent retry.
+pub struct MockScanner {
+    powered: Cell<bool>,
+    power_available: bool,
+    cursor: Rc<Cell<usize>>,
+    advertisements: Rc<[MockAdvertisement]>,
+    devices: Rc<MockDevices>,
+}
+
+/// The devices known to a [`MockScanner`] along with the subscribers
+/// to property changes of those devices.
+#[derive(Default)]
+struct MockDevices {
+    advertisements: RefCell<HashMap<Address, MockAdvertisement>>,
+    subscribers: RefCell<Vec<(Address, UnboundedSender<DeviceEvent>)>>,
+}
+
+impl MockDevices {
+    /// Records the advertisement as the current state of the device,
+    /// notifying any subscribers of the changed properties, and
+    /// returns true if the device wasn't previously known.
+    fn update(&self, advertisement: &MockAdvertisement) -> bool {
+        let address = advertisement.address;
+        let previous = self
+            .advertisements
+            .borrow_mut()
+            .insert(address, advertisement.clone());
+
+        let mut changes = Vec::new();
+        if let Some(rssi) = advertisement.rssi {
+            changes.push(DeviceProperty::Rssi(rssi));
+        }
+        if let Some(tx_power) = advertisement.tx_power {
+            changes.push(DeviceProperty::TxPower(tx_power));
+        }
+        changes.push(DeviceProperty::ManufacturerData(
+            advertisement.manufacturer_data.clone(),
+        ));
+
+        self.subscribers
+            .borrow_mut()
+            .retain(|(subscribed, sender)| {
+                *subscribed != address
+                    || changes.iter().all(|change| {
+                        sender
+                            .unbounded_send(DeviceEvent::PropertyChanged(change.clone()))
+                            .is_ok()
+                    })
+            });
+
+        previous.is_none()
+    }
+}
+
+impl MockScanner {
+    /// Creates a new, powered off, [`MockScanner`] that will emit the
+    /// supplied advertisements once discovery has been started.
+    pub fn new(advertisements: Vec<MockAdvertisement>) -> Self {
+        Self {
+            powered: Cell::new(false),
+            power_available: true,
+            cursor: Rc::new(Cell::new(0)),
+            advertisements: advertisements.into(),
+            devices: Rc::new(MockDevices::default()),
+        }
+    }
+
+    /// Simulates an adapter that fails to power on, for example
+    /// because it has been blocked by rfkill.
+    #[cfg(test)]
+    pub fn without_power(mut self) -> Self {
+        self.power_available = false;
+        self
+    }
+
+    fn error(kind: ErrorKind, message: &str) -> bluer::Error {
+        bluer::Error {
+            kind,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Scanner for MockScanner {
+    type Device = MockDevice;
+
+    async fn set_powered(&self, powered: bool) -> bluer::Result<()> {
+        if powered && !self.power_available {
+            return Err(Self::error(
+                ErrorKind::NotReady,
+                "adapter cannot be powered",
+            ));
+        }
+
+        self.powered.set(powered);
+        Ok(())
+    }
+
+    async fn set_discovery_filter(&self, _: DiscoveryFilter) -> bluer::Result<()> {
+        Ok(())
+    }
+
+    async fn discover_devices(&self) -> bluer::Result<LocalBoxStream<'static, AdapterEvent>> {
+        if !self.powered.get() {
+            return Err(Self::error(ErrorKind::NotReady, "adapter is not powered"));
+        }
+
+        let state = (
+            self.cursor.clone(),
+            self.advertisements.clone(),
+            self.devices.clone(),
+            HashSet::new(),
+        );
+
+        Ok(futures::stream::unfold(state, |state| async move {
+            let (cursor, advertisements, devices, mut seen) = state;
+
+            loop {
+                let Some(advertisement) = advertisements.get(cursor.get()) else {
+                    // Once the script is exhausted the radio simply goes
+                    // quiet, just like a real adapter with nothing in range.
+                    futures::future::pending::<()>().await;
+                    unreachable!("pending future never completes");
+                };
+
+                tokio::time::sleep(advertisement.delay).await;
+                cursor.set(cursor.get() + 1);
+
+                let address = advertisement.address;
+                devices.update(advertisement);
+
+                if seen.insert(address) {
+                    return Some((
+                        AdapterEvent::DeviceAdded(address),
+                        (cursor, advertisements, devices, seen),
+                    ));
+                }
+            }
+        })
+        .boxed_local())
+    }
+
+    async fn device_addresses(&self) -> bluer::Result<Vec<Address>> {
+        Ok(self
+            .devices
+            .advertisements
+            .borrow()
+            .keys()
+            .copied()
+            .collect())
+    }
+
+    fn device(&self, address: Address) -> bluer::Result<MockDevice> {
+        Ok(MockDevice {
+            address,
+            devices: self.devices.clone(),
+        })
+    }
+}
+
+/// A device known to a [`MockScanner`], its properties reflect the
+/// most recent advertisement emitted for its address.
+#[derive(Clone)]
+pub struct MockDevice {
+    address: Address,
+    devices: Rc<MockDevices>,
+}
+
+impl MockDevice {
+    fn with_advertisement<R>(&self, f: impl FnOnce(&MockAdvertisement) -> R) -> bluer::Result<R> {
+        match self.devices.advertisements.borrow().get(&self.address) {
+            Some(advertisement) => Ok(f(advertisement)),
+            None => Err(MockScanner::error(
+                ErrorKind::DoesNotExist,
+                "device has not been discovered",
+            )),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ScannedDevice for MockDevice {
+    #[cfg(feature = "cli")]
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn rssi(&self) -> bluer::Result<Option<i16>> {
+        self.with_advertisement(|advertisement| advertisement.rssi)
+    }
+
+    #[cfg(feature = "cli")]
+    async fn tx_power(&self) -> bluer::Result<Option<i16>> {
+        self.with_advertisement(|advertisement| advertisement.tx_power)
+    }
+
+    async fn manufacturer_data(&self) -> bluer::Result<Option<HashMap<u16, Vec<u8>>>> {
+        self.with_advertisement(|advertisement| Some(advertisement.manufacturer_data.clone()))
+    }
+
+    async fn events(&self) -> bluer::Result<LocalBoxStream<'static, DeviceEvent>> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        self.devices
+            .subscribers
+            .borrow_mut()
+            .push((self.address, sender));
+
+        Ok(receiver.boxed_local())
+    }
+}
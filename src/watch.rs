@@ -3,29 +3,35 @@ use crate::lib::watch::AppleWatchError::{
     RetriesExceeded,
 };
 
-use aes::cipher::block_padding::NoPadding;
-use bluer::{Adapter, AdapterEvent, Address, Device, DiscoveryFilter, DiscoveryTransport};
-use ecb::cipher::{BlockEncryptMut, KeyInit};
+#[cfg(feature = "cli")]
+use crate::lib::continuity::ContinuityMessageType;
+use crate::lib::continuity::{ContinuityError, NearbyInfo};
+use crate::lib::irk::{Irk, IrkResolver};
+use crate::lib::scanner::{ScannedDevice, Scanner};
+
+use bluer::{
+    AdapterEvent, Address, DeviceEvent, DeviceProperty, DiscoveryFilter, DiscoveryTransport,
+};
 use futures::StreamExt;
+use futures::stream::{LocalBoxStream, SelectAll};
+use std::collections::HashSet;
 use std::time::Duration;
 use thiserror::Error;
 use tokio::time::timeout;
 
-type Aes128EcbEnc = ecb::Encryptor<aes::Aes128>;
-
-pub struct AppleWatch {
-    identity_resolution_key: [u8; 16],
-    device: Option<Device>,
+pub struct AppleWatch<D: ScannedDevice> {
+    resolver: IrkResolver,
+    device: Option<D>,
 }
 
-impl AppleWatch {
+impl<D: ScannedDevice> AppleWatch<D> {
     /// Creates a new [`AppleWatch`] that can be used to search
     /// for, and obtain the status of, an Apple Watch that has
     /// a Bluetooth address matching the supplied Identity Resolution
     /// Key.
-    pub fn new(irk: [u8; 16]) -> Self {
+    pub fn new(irk: Irk) -> Self {
         Self {
-            identity_resolution_key: irk,
+            resolver: IrkResolver::new([irk]),
             device: None,
         }
     }
@@ -36,9 +42,12 @@ impl AppleWatch {
     /// This function will attempt multiple times to discover the device,
     /// when the watch is found it will return the number of tries it took
     /// to find it.
-    pub async fn find_watch(
+    ///
+    /// The search is performed using the supplied [`Scanner`], which is
+    /// normally a [`bluer::Adapter`] but can be any other backend.
+    pub async fn find_watch<S: Scanner<Device = D>>(
         &mut self,
-        adapter: &Adapter,
+        adapter: &S,
         retries: u8,
         retry_timeout: Duration,
     ) -> Result<u8, AppleWatchError> {
@@ -71,72 +80,117 @@ impl AppleWatch {
     }
 
     /// Consumes device discovery events from the Bluetooth adapter
-    /// till a device is discovered that has an address that matches,
-    /// via [`AppleWatch::is_matching_watch_address`], the Apple Watch
-    /// being searched for.
-    async fn find_watch_internal(
+    /// till a device is discovered that has an address that resolves,
+    /// via [`IrkResolver::resolves`], to the Apple Watch being searched
+    /// for.
+    ///
+    /// Devices already known to the adapter are checked first, as the
+    /// adapter isn't guaranteed to announce them again, and matching
+    /// devices without an RSSI are followed for property changes till
+    /// one becomes available.
+    async fn find_watch_internal<S: Scanner<Device = D>>(
         &self,
-        adapter: &Adapter,
-    ) -> Result<Option<Device>, AppleWatchError> {
+        adapter: &S,
+    ) -> Result<Option<D>, AppleWatchError> {
         let mut device_events = AppleWatchError::wrap_bluetooth_action("discover devices", || {
             adapter.discover_devices()
         })
         .await?;
 
+        let known_addresses = AppleWatchError::wrap_bluetooth_action("get known devices", || {
+            adapter.device_addresses()
+        })
+        .await?;
+
+        let mut followed_devices = HashSet::new();
+        let mut property_events = SelectAll::new();
+
+        for addr in known_addresses {
+            if let Some(device) = self
+                .check_candidate(adapter, addr, &mut followed_devices, &mut property_events)
+                .await?
+            {
+                return Ok(Some(device));
+            }
+        }
+
         loop {
             tokio::select! {
                 Some(device_event) = device_events.next() => {
-                    match device_event {
-                        AdapterEvent::DeviceAdded(addr) if self.is_matching_watch_address(addr) => {
-                            // Before returning a successful discovery, first make
-                            // sure an RSSI value is available otherwise it won't
-                            // be possible to use it for unlocking a user session.
-                            let device = match adapter.device(addr) {
-                                Err(err) => return Err(BluetoothError {
-                                    action: "get Apple Watch device",
-                                    source: err
-                                }),
-                                Ok(device) => device
-                            };
-
-                            let rssi = AppleWatchError::wrap_bluetooth_action("get device RSSI", || {
-                                device.rssi()
-                            }).await?;
-
-                            return match rssi {
-                                Some(_) => Ok(Some(device)),
-                                None => Ok(None)
-                            }
-                        }
-                        _ => ()
+                    if let AdapterEvent::DeviceAdded(addr) = device_event
+                        && let Some(device) = self
+                            .check_candidate(adapter, addr, &mut followed_devices, &mut property_events)
+                            .await?
+                    {
+                        return Ok(Some(device));
+                    }
+                }
+                Some((device, DeviceEvent::PropertyChanged(property))) = property_events.next() => {
+                    if !matches!(property, DeviceProperty::Rssi(_) | DeviceProperty::ManufacturerData(_)) {
+                        continue;
+                    }
+
+                    let rssi = AppleWatchError::wrap_bluetooth_action("get device RSSI", || {
+                        device.rssi()
+                    }).await?;
+
+                    if rssi.is_some() {
+                        return Ok(Some(device));
                     }
                 }
+                else => return Ok(None),
             }
         }
     }
 
-    /// Determines if the supplied Bluetooth address matches
-    /// the desired Apple Watch by using the Identity Resolution Key
-    /// to encrypt, using AES-128 ECB, the top 3 bytes of the address
-    /// and comparing the result against the bottom 3 bytes of the address.
+    /// Checks if the device with the supplied address is the Apple Watch
+    /// being searched for and returns it if an RSSI is available.
     ///
-    /// If the encrypted top bytes match the bottom bytes then the Bluetooth
-    /// address is that of the Apple Watch desired.
-    fn is_matching_watch_address(&self, addr: Address) -> bool {
-        if (addr.0[0] >> 6) != 0x01 {
-            return false;
+    /// If the device matches but has no RSSI yet, it is followed for
+    /// property changes via `property_events`.
+    async fn check_candidate<S: Scanner<Device = D>>(
+        &self,
+        adapter: &S,
+        addr: Address,
+        followed_devices: &mut HashSet<Address>,
+        property_events: &mut SelectAll<LocalBoxStream<'static, (D, DeviceEvent)>>,
+    ) -> Result<Option<D>, AppleWatchError> {
+        if !self.resolver.resolves(addr) {
+            return Ok(None);
         }
 
-        let (top, bottom) = (&addr.0[..3], &addr.0[3..]);
+        // Before returning a successful discovery, first make
+        // sure an RSSI value is available otherwise it won't
+        // be possible to use it for unlocking a user session.
+        let device = match adapter.device(addr) {
+            Err(err) => {
+                return Err(BluetoothError {
+                    action: "get Apple Watch device",
+                    source: err,
+                });
+            }
+            Ok(device) => device,
+        };
+
+        let rssi =
+            AppleWatchError::wrap_bluetooth_action("get device RSSI", || device.rssi()).await?;
+        if rssi.is_some() {
+            return Ok(Some(device));
+        }
 
-        let mut buf: [u8; 16] = [0; 16];
-        buf[13..16].copy_from_slice(top);
+        if followed_devices.insert(addr) {
+            let events =
+                AppleWatchError::wrap_bluetooth_action("follow device changes", || device.events())
+                    .await?;
 
-        let hashed_address = Aes128EcbEnc::new(&self.identity_resolution_key.into())
-            .encrypt_padded_mut::<NoPadding>(&mut buf, 16)
-            .expect("address hash");
+            property_events.push(
+                events
+                    .map(move |event| (device.clone(), event))
+                    .boxed_local(),
+            );
+        }
 
-        bottom == &hashed_address[13..16]
+        Ok(None)
     }
 
     /// Specifies the 16-bit unsigned integer Company Identifier
@@ -145,25 +199,66 @@ impl AppleWatch {
     /// Reference: <https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Assigned_Numbers/out/en/Assigned_Numbers.pdf>
     const MANUFACTURER_CODE_APPLE: u16 = 0x004c;
 
-    /// Specifies the 8-bit unsigned integer Apple Continuity
-    /// message type for Nearby Information messages.
+    /// Collects up to `samples` received signal strength indicator values
+    /// for the Apple Watch found by [`AppleWatch::find_watch`], waiting at most
+    /// `window` for the watch to advertise new values.
     ///
-    /// Reference: <https://github.com/furiousMAC/continuity/blob/master/messages/nearby_info.md>
-    const NEARBY_INFO_MESSAGE: u8 = 0x10;
-
-    /// Specifies the bit-flag for the Apple Watch Locked status
-    /// within the data flags segment of a Nearby Information
-    /// message.
+    /// The current RSSI is always the first sample, discovery is restarted
+    /// for the duration of the window as the adapter only updates the RSSI
+    /// of a device whilst it is discovering.
     ///
-    /// Reference: <https://github.com/furiousMAC/continuity/blob/master/messages/nearby_info.md>
-    const NEARBY_INFO_DATA_FLAG_WATCH_LOCKED: u8 = 0x20;
+    /// ### Panics
+    /// This function expects that [`AppleWatch::find_watch`] has been called first
+    /// to identify the target Bluetooth device from which to collect samples.
+    pub async fn sample_rssi<S: Scanner<Device = D>>(
+        &self,
+        adapter: &S,
+        samples: u8,
+        window: Duration,
+    ) -> Result<Vec<i16>, AppleWatchError> {
+        let device = self.device.clone().expect("device already found");
 
-    /// Specifies the bit-flag for the Apple Watch Auto Unlock,
-    /// for other devices (e.g. Macbook or iPhone), within the
-    /// data flags segment of a Nearby Information message.
-    ///
-    /// Reference: <https://github.com/furiousMAC/continuity/blob/master/messages/nearby_info.md>
-    const NEARBY_INFO_DATA_FLAG_AUTO_UNLOCK_ENABLED: u8 = 0x80;
+        let mut rssi_samples = Vec::with_capacity(usize::from(samples));
+        if let Some(rssi) =
+            AppleWatchError::wrap_bluetooth_action("get device RSSI", || device.rssi()).await?
+        {
+            rssi_samples.push(rssi);
+        }
+
+        if rssi_samples.len() >= usize::from(samples) {
+            return Ok(rssi_samples);
+        }
+
+        let mut property_events =
+            AppleWatchError::wrap_bluetooth_action("follow device changes", || device.events())
+                .await?;
+
+        let mut device_events = AppleWatchError::wrap_bluetooth_action("discover devices", || {
+            adapter.discover_devices()
+        })
+        .await?;
+
+        let deadline = tokio::time::sleep(window);
+        tokio::pin!(deadline);
+
+        while rssi_samples.len() < usize::from(samples) {
+            tokio::select! {
+                () = &mut deadline => break,
+
+                // Discovery events aren't needed, but the stream must
+                // be polled to keep the discovery session alive.
+                Some(_) = device_events.next() => (),
+                Some(DeviceEvent::PropertyChanged(property)) = property_events.next() => {
+                    if let DeviceProperty::Rssi(rssi) = property {
+                        rssi_samples.push(rssi);
+                    }
+                }
+                else => break,
+            }
+        }
+
+        Ok(rssi_samples)
+    }
 
     /// Returns an [`AppleWatchStatus`] for this [`AppleWatch`] by extracting
     /// the information from the manufacturer data advertised by the Apple Watch
@@ -197,32 +292,35 @@ impl AppleWatch {
                 },
             };
 
-        let message_header = apple_data
-            .get(..2)
-            .ok_or(AppleContinuityMessageError("header unavailable"))?;
-
-        if message_header[0] != Self::NEARBY_INFO_MESSAGE {
-            return Err(AppleContinuityMessageError("expected Nearby Info message"));
-        } else if message_header[1] < 0x5 {
-            return Err(AppleContinuityMessageError(
-                "Nearby Info message is less than 5 bytes long",
-            ));
-        }
-
-        let message = apple_data.get(2..5).ok_or(AppleContinuityMessageError(
-            "Nearby Info message data unavailable",
-        ))?;
+        // Only the CLI shows the other messages advertised by the watch
+        #[cfg_attr(not(feature = "cli"), expect(unused_variables))]
+        let Some((nearby_info, other_messages)) = NearbyInfo::find(&apple_data)? else {
+            return Err(AppleContinuityMessageError("Nearby Info message not found"));
+        };
 
-        let data_flags = message[1];
         Ok(AppleWatchStatus {
             rssi,
-            locked: (data_flags & Self::NEARBY_INFO_DATA_FLAG_WATCH_LOCKED) != 0x0,
-            device_auto_unlock_enabled: (data_flags
-                & Self::NEARBY_INFO_DATA_FLAG_AUTO_UNLOCK_ENABLED)
-                != 0,
+            locked: nearby_info.watch_locked(),
+            device_auto_unlock_enabled: nearby_info.auto_unlock_enabled(),
+            #[cfg(feature = "cli")]
+            nearby_info,
+            #[cfg(feature = "cli")]
+            other_messages,
         })
     }
 
+    /// Returns the transmission power advertised by the Apple Watch
+    /// found by [`AppleWatch::find_watch`], if it advertised one.
+    ///
+    /// ## Panics
+    /// A panic will be thrown if [`AppleWatch::find_watch`] has not been called
+    /// successfully before invoking this function.
+    #[cfg(feature = "cli")]
+    pub async fn get_watch_tx_power(&self) -> Result<Option<i16>, AppleWatchError> {
+        let device = self.device.clone().expect("device already found");
+        AppleWatchError::wrap_bluetooth_action("get device TX power", || device.tx_power()).await
+    }
+
     /// Returns the [`bluer::Address`] of the Apple Watch
     /// found by [`AppleWatch::find_watch`].
     ///
@@ -251,6 +349,16 @@ pub struct AppleWatchStatus {
     /// Specifies if the Apple Watch is configured to allow
     /// for the unlocking of remote devices (e.g. Macbook, iPhone).
     pub device_auto_unlock_enabled: bool,
+
+    /// Specifies the full Nearby Information message advertised
+    /// by the Apple Watch from which this status was derived.
+    #[cfg(feature = "cli")]
+    pub nearby_info: NearbyInfo,
+
+    /// Specifies the types of any other Apple Continuity messages
+    /// advertised alongside the Nearby Information message.
+    #[cfg(feature = "cli")]
+    pub other_messages: Vec<ContinuityMessageType>,
 }
 
 #[derive(Error, Debug)]
@@ -273,6 +381,9 @@ pub enum AppleWatchError {
 
     #[error("Apple Continuity message invalid: {0}")]
     AppleContinuityMessageError(&'static str),
+
+    #[error("Apple Continuity message could not be decoded: {0}")]
+    AppleContinuityDecodeError(#[from] ContinuityError),
 }
 
 impl AppleWatchError {
@@ -292,3 +403,92 @@ impl AppleWatchError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AppleWatch;
+    use crate::lib::irk::Irk;
+    use crate::lib::scanner::mock::{MockAdvertisement, MockDevice, MockScanner};
+
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// Specifies an IRK and an address that resolves with it.
+    const WATCH_IRK: &str = "XkVgPxNEK0p4TDgZegzDUA==";
+    const WATCH_ADDRESS: &str = "4A:11:22:95:3D:A2";
+
+    /// Returns an advertisement of the watch, with an unlocked
+    /// Nearby Info message.
+    fn advertisement(delay: Duration, rssi: Option<i16>) -> MockAdvertisement {
+        MockAdvertisement {
+            delay,
+            address: WATCH_ADDRESS.parse().unwrap(),
+            rssi,
+            tx_power: None,
+            manufacturer_data: HashMap::from([(
+                0x004c,
+                vec![0x10, 0x05, 0x01, 0x80, 0xAA, 0xBB, 0xCC],
+            )]),
+        }
+    }
+
+    /// Searches for the watch once, returning the watch along with
+    /// the number of tries it took to find it, if it was found.
+    fn find(scanner: &MockScanner) -> (AppleWatch<MockDevice>, Option<u8>) {
+        let mut watch = AppleWatch::new(WATCH_IRK.parse::<Irk>().unwrap());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let tries = runtime
+            .block_on(watch.find_watch(scanner, 1, Duration::from_millis(200)))
+            .ok();
+
+        (watch, tries)
+    }
+
+    #[test]
+    fn known_watch_is_found_without_being_announced_again() {
+        let scanner = MockScanner::new(vec![advertisement(Duration::ZERO, Some(-60))]);
+        assert_eq!(find(&scanner).1, Some(1));
+
+        // The script is exhausted, so the second search can only find
+        // the watch among the devices already known to the adapter.
+        let (watch, tries) = find(&scanner);
+        assert_eq!(tries, Some(1));
+
+        let status = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(watch.get_watch_status())
+            .unwrap();
+        assert_eq!(status.rssi, -60);
+    }
+
+    #[test]
+    fn watch_is_found_once_its_rssi_changes() {
+        let scanner = MockScanner::new(vec![
+            advertisement(Duration::ZERO, None),
+            advertisement(Duration::from_millis(20), Some(-65)),
+        ]);
+
+        let (watch, tries) = find(&scanner);
+        assert_eq!(tries, Some(1));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let status = runtime.block_on(watch.get_watch_status()).unwrap();
+        assert_eq!(status.rssi, -65);
+        assert!(status.device_auto_unlock_enabled);
+    }
+
+    #[test]
+    fn watch_without_rssi_is_not_found() {
+        let scanner = MockScanner::new(vec![advertisement(Duration::ZERO, None)]);
+        assert_eq!(find(&scanner).1, None);
+    }
+}